@@ -0,0 +1,337 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    cfg::Cfg,
+    instruction::{signature, ArgKind},
+    program::{is_device, is_literal, Instruction, Program},
+};
+
+/// One of the IC's registers, or a virtual register to be allocated one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Register {
+    R(usize),
+    Ra,
+    Sp,
+    /// Named register, by index among the [`Aliases::virtuals`] of its program
+    Virtual(usize),
+}
+
+impl Register {
+    /// Register named by `token` itself (`r0`..., `ra`, `sp`), aliases aside
+    pub fn parse(token: &str) -> Option<Self> {
+        match token {
+            "ra" => Some(Register::Ra),
+            "sp" => Some(Register::Sp),
+            _ => token
+                .strip_prefix('r')
+                .and_then(|i| i.parse().ok())
+                .map(Register::R),
+        }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        matches!(self, Register::Virtual(_))
+    }
+}
+
+pub type RegisterSet = BTreeSet<Register>;
+
+/// Registers a name may stand for, from every `alias` binding it in the program.
+///
+/// This is flow-insensitive: a name bound to several registers may stand for any of them.
+/// Names written as registers without being bound by an `alias` (or a `define`, or being a
+/// label) are virtual registers.
+#[derive(Clone, Debug, Default)]
+pub struct Aliases {
+    bindings: HashMap<String, Vec<String>>,
+    labels: BTreeSet<String>,
+    defines: BTreeSet<String>,
+    /// Names of the virtual registers, sorted, so that they are ordered by name
+    virtuals: Vec<String>,
+}
+
+impl Aliases {
+    pub fn of(program: &Program) -> Self {
+        let mut aliases = Self {
+            labels: program.labels().into_keys().collect(),
+            ..Self::default()
+        };
+        for (_, instr) in program.instructions() {
+            match (instr.op.as_str(), instr.args.as_slice()) {
+                ("alias", [name, target]) => {
+                    let targets = aliases.bindings.entry(name.clone()).or_default();
+                    if !targets.contains(target) {
+                        targets.push(target.clone());
+                    }
+                }
+                ("define", [name, _]) => {
+                    aliases.defines.insert(name.clone());
+                }
+                _ => {}
+            }
+        }
+        let mut virtuals = BTreeSet::new();
+        for (_, instr) in program.instructions() {
+            let kinds = match signature(&instr.op) {
+                Some(kinds) if kinds.len() == instr.args.len() => kinds,
+                _ => continue,
+            };
+            for (arg, kind) in instr.args.iter().zip(kinds) {
+                if *kind == ArgKind::Register && aliases.is_virtual(arg) {
+                    virtuals.insert(arg.clone());
+                }
+            }
+        }
+        aliases.virtuals = virtuals.into_iter().collect();
+        aliases
+    }
+
+    /// Whether `token` would be a virtual register, if written
+    fn is_virtual(&self, token: &str) -> bool {
+        !(self.bindings.contains_key(token)
+            || Register::parse(token).is_some()
+            || is_literal(token)
+            || is_device(token)
+            || self.labels.contains(token)
+            || self.defines.contains(token))
+    }
+
+    /// Names of the virtual registers, sorted
+    pub fn virtuals(&self) -> impl Iterator<Item = &String> {
+        self.virtuals.iter()
+    }
+
+    /// Virtual register called `name`, the same one for every use of the name
+    pub fn virtual_register(&self, name: &str) -> Option<Register> {
+        self.virtuals
+            .binary_search_by(|n| n.as_str().cmp(name))
+            .ok()
+            .map(Register::Virtual)
+    }
+
+    /// Name of register `r`, as written in the program
+    pub fn name(&self, r: Register) -> String {
+        match r {
+            Register::R(i) => format!("r{}", i),
+            Register::Ra => "ra".to_owned(),
+            Register::Sp => "sp".to_owned(),
+            Register::Virtual(i) => self.virtuals[i].clone(),
+        }
+    }
+
+    /// Registers `token` may stand for, `None` if it could be any register
+    pub fn registers(&self, token: &str) -> Option<RegisterSet> {
+        let mut regs = RegisterSet::new();
+        if self.collect(token, &mut regs, 0) {
+            Some(regs)
+        } else {
+            None
+        }
+    }
+
+    fn collect(&self, token: &str, regs: &mut RegisterSet, depth: usize) -> bool {
+        if let Some(targets) = self.bindings.get(token) {
+            depth < self.bindings.len() && targets.iter().all(|t| self.collect(t, regs, depth + 1))
+        } else if let Some(r) = Register::parse(token) {
+            regs.insert(r);
+            true
+        } else if let Some(r) = self.virtual_register(token) {
+            regs.insert(r);
+            true
+        } else {
+            is_literal(token)
+                || is_device(token)
+                || self.labels.contains(token)
+                || self.defines.contains(token)
+        }
+    }
+
+    /// The one register `token` stands for, if it is certain
+    pub fn register(&self, token: &str) -> Option<Register> {
+        self.registers(token)
+            .filter(|regs| regs.len() == 1)
+            .and_then(|regs| regs.into_iter().next())
+    }
+}
+
+/// Registers an instruction reads and writes, and whether it does anything else
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Effects {
+    /// Registers certainly written
+    pub defs: RegisterSet,
+    /// Registers possibly written
+    pub may_defs: RegisterSet,
+    /// Writes registers that are not known before running the program
+    pub defs_unknown: bool,
+    pub uses: RegisterSet,
+    /// Reads registers that are not known before running the program
+    pub uses_unknown: bool,
+    /// Writes devices or the stack, jumps, yields, binds names or draws a random number
+    pub side_effects: bool,
+}
+
+/// Instructions doing more than writing registers from their operands (`rand` gives
+/// another value every time)
+const SIDE_EFFECTS: &[&str] = &[
+    "s", "sb", "sbn", "sbs", "sd", "push", "pop", "peek", "put", "yield", "sleep", "hcf", "alias",
+    "define", "rand",
+];
+
+impl Effects {
+    pub fn of(instr: &Instruction, aliases: &Aliases) -> Self {
+        let mut effects = Self::default();
+        let kinds = match signature(&instr.op) {
+            Some(kinds) if kinds.len() == instr.args.len() => kinds,
+            // Invalid instructions fail at runtime, leave them be
+            _ => {
+                effects.defs_unknown = true;
+                effects.uses_unknown = true;
+                effects.side_effects = true;
+                return effects;
+            }
+        };
+        for (arg, kind) in instr.args.iter().zip(kinds) {
+            match (kind, aliases.registers(arg)) {
+                (ArgKind::Register, Some(regs)) if regs.len() == 1 => effects.defs.extend(regs),
+                (ArgKind::Register, Some(regs)) => effects.may_defs.extend(regs),
+                (ArgKind::Register, None) => effects.defs_unknown = true,
+                (ArgKind::Number, Some(regs)) | (ArgKind::Line, Some(regs)) => {
+                    effects.uses.extend(regs)
+                }
+                (ArgKind::Number, None) | (ArgKind::Line, None) => effects.uses_unknown = true,
+                _ => {}
+            }
+        }
+        if let Some(branch) = instr.branch() {
+            effects.side_effects = true;
+            if branch.link {
+                effects.defs.insert(Register::Ra);
+            }
+        }
+        if matches!(instr.op.as_str(), "push" | "pop" | "peek") {
+            effects.uses.insert(Register::Sp);
+            if instr.op != "peek" {
+                effects.defs.insert(Register::Sp);
+            }
+        }
+        effects.side_effects |= SIDE_EFFECTS.contains(&instr.op.as_str());
+        effects.may_defs.extend(effects.defs.iter().cloned());
+        effects
+    }
+
+    /// Whether the instruction does nothing but write registers in `defs`
+    pub fn is_pure(&self) -> bool {
+        !self.side_effects && !self.defs_unknown && self.may_defs == self.defs
+    }
+}
+
+/// Registers live (holding a value that may still be read) before and after every line
+#[derive(Clone, Debug)]
+pub struct Liveness {
+    pub live_in: Vec<RegisterSet>,
+    pub live_out: Vec<RegisterSet>,
+}
+
+impl Liveness {
+    pub fn compute(program: &Program, cfg: &Cfg) -> Self {
+        let aliases = Aliases::of(program);
+        let effects: Vec<Option<Effects>> = program
+            .lines
+            .iter()
+            .map(|l| l.instruction().map(|i| Effects::of(i, &aliases)))
+            .collect();
+        // Registers read by instructions whose operands are not known
+        let all: RegisterSet = effects
+            .iter()
+            .flatten()
+            .flat_map(|e| e.uses.iter().chain(e.may_defs.iter()).cloned())
+            .chain(vec![Register::Ra, Register::Sp])
+            .collect();
+
+        let n = program.len();
+        let mut live = Self {
+            live_in: vec![RegisterSet::new(); n],
+            live_out: vec![RegisterSet::new(); n],
+        };
+        let nodes: Vec<_> = cfg.blocks().collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (node, block) in nodes.iter().rev() {
+                let mut regs: RegisterSet = cfg
+                    .successors(*node)
+                    .iter()
+                    .flat_map(|(s, _)| live.live_in[cfg.block(*s).start].iter().cloned())
+                    .collect();
+                for line in block.lines().rev() {
+                    live.live_out[line] = regs.clone();
+                    if let Some(e) = &effects[line] {
+                        for r in e.defs.iter() {
+                            regs.remove(r);
+                        }
+                        if e.uses_unknown {
+                            regs.extend(all.iter().cloned());
+                        } else {
+                            regs.extend(e.uses.iter().cloned());
+                        }
+                    }
+                    if live.live_in[line] != regs {
+                        live.live_in[line] = regs.clone();
+                        changed = true;
+                    }
+                }
+            }
+        }
+        live
+    }
+
+    /// Whether `reg` may be read after line `line`
+    pub fn is_live_after(&self, line: usize, reg: Register) -> bool {
+        self.live_out
+            .get(line)
+            .is_some_and(|regs| regs.contains(&reg))
+    }
+}
+
+/// Forward data-flow analysis: the state before every reachable line, starting from `entry`
+/// on the first line. `join` merges the states of two paths, `transfer` steps a state over
+/// an instruction.
+pub fn forward<S, J, T>(
+    program: &Program,
+    cfg: &Cfg,
+    entry: S,
+    join: J,
+    transfer: T,
+) -> Vec<Option<S>>
+where
+    S: Clone + PartialEq,
+    J: Fn(&S, &S) -> S,
+    T: Fn(&Instruction, &mut S),
+{
+    let mut state_in: Vec<Option<S>> = vec![None; program.len()];
+    let mut worklist = match cfg.entry() {
+        Some(node) => vec![(node, entry)],
+        None => return state_in,
+    };
+    while let Some((node, incoming)) = worklist.pop() {
+        let block = cfg.block(node);
+        let merged = match &state_in[block.start] {
+            Some(s) => join(s, &incoming),
+            None => incoming,
+        };
+        if state_in[block.start].as_ref() == Some(&merged) {
+            continue;
+        }
+        let mut state = merged;
+        for line in block.lines() {
+            state_in[line] = Some(state.clone());
+            if let Some(instr) = program.lines[line].instruction() {
+                transfer(instr, &mut state);
+            }
+        }
+        for (succ, _) in cfg.successors(node) {
+            worklist.push((succ, state.clone()));
+        }
+    }
+    state_in
+}
@@ -0,0 +1,279 @@
+use std::f64::consts::PI;
+use std::fmt::Debug;
+
+use crate::device::DeviceType;
+
+/// Length of a single game tick in seconds
+pub const TICK_SECONDS: f64 = 0.5;
+
+/// Helper trait so boxed behaviours can be cloned along with their device.
+///
+/// Implemented for every `DeviceBehavior` that is also `Clone`.
+pub trait BehaviorClone {
+    fn clone_box(&self) -> Box<dyn DeviceBehavior>;
+}
+
+impl<T: 'static + DeviceBehavior + Clone> BehaviorClone for T {
+    fn clone_box(&self) -> Box<dyn DeviceBehavior> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn DeviceBehavior> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Behaviour of a simulated device, invoked once per simulation tick.
+///
+/// A behaviour reads the device's settings (e.g. `On`, `Mode`) and updates its other
+/// parameters accordingly, so control scripts have something that responds.
+pub trait DeviceBehavior: BehaviorClone + Debug {
+    fn tick(&mut self, device: &mut DeviceType);
+}
+
+/// Active vent moving room `Pressure` towards `PressureExternal` while `On` is 1.
+///
+/// `Mode` 0 (outward) raises the pressure, `Mode` 1 (inward) lowers it.
+#[derive(Clone, Debug)]
+pub struct ActiveVent {
+    /// Maximum pressure change (kPa) per tick
+    pub rate: f64,
+}
+
+impl ActiveVent {
+    pub fn new(rate: f64) -> Self {
+        Self { rate }
+    }
+}
+
+impl Default for ActiveVent {
+    fn default() -> Self {
+        Self::new(10.0)
+    }
+}
+
+impl DeviceBehavior for ActiveVent {
+    fn tick(&mut self, device: &mut DeviceType) {
+        if device.get_param("On") < 1.0 {
+            return;
+        }
+        let p = device.get_param("Pressure");
+        let target = device.get_param("PressureExternal");
+        let p = if device.get_param("Mode") < 1.0 {
+            if p < target {
+                f64::min(p + self.rate, target)
+            } else {
+                p
+            }
+        } else if p > target {
+            f64::max(p - self.rate, target)
+        } else {
+            p
+        };
+        device.set_param("Pressure", p);
+    }
+}
+
+/// Daylight sensor following a sinusoidal sun curve.
+///
+/// Sets `Horizontal` (sun azimuth in degrees), `Vertical` (sun angle from zenith in degrees)
+/// and `Activate` (1 while the sun is above the horizon).
+#[derive(Clone, Debug)]
+pub struct DaylightSensor {
+    /// Length of a full day in ticks
+    pub day_length: usize,
+    pub tick: usize,
+}
+
+impl DaylightSensor {
+    pub fn new(day_length: usize) -> Self {
+        Self {
+            day_length,
+            tick: 0,
+        }
+    }
+}
+
+impl Default for DaylightSensor {
+    fn default() -> Self {
+        // Earth-like day of 20 minutes
+        Self::new(2400)
+    }
+}
+
+impl DeviceBehavior for DaylightSensor {
+    fn tick(&mut self, device: &mut DeviceType) {
+        let phase = (self.tick % self.day_length) as f64 / self.day_length as f64;
+        let elevation = 90.0 * (2.0 * PI * phase).sin();
+        device.set_param("Horizontal", 360.0 * phase);
+        device.set_param("Vertical", 90.0 - elevation);
+        device.set_param("Activate", if elevation > 0.0 { 1.0 } else { 0.0 });
+        self.tick += 1;
+    }
+}
+
+/// Battery charging from `PowerInput` and draining by `PowerOutput` (both in watts).
+///
+/// `Charge` (joules) is clamped to `[0, Maximum]` and `Ratio` is kept up to date.
+#[derive(Clone, Debug)]
+pub struct Battery;
+
+impl DeviceBehavior for Battery {
+    fn tick(&mut self, device: &mut DeviceType) {
+        let max = device.get_param("Maximum");
+        let delta =
+            (device.get_param("PowerInput") - device.get_param("PowerOutput")) * TICK_SECONDS;
+        let charge = (device.get_param("Charge") + delta).max(0.0).min(max);
+        device.set_param("Charge", charge);
+        device.set_param("Ratio", if max > 0.0 { charge / max } else { 0.0 });
+    }
+}
+
+/// Heater raising `Temperature` while `On` is 1, otherwise drifting towards `ambient`.
+#[derive(Clone, Debug)]
+pub struct Heater {
+    /// Temperature increase (K) per tick while on
+    pub rate: f64,
+    /// Temperature (K) the room returns to while off
+    pub ambient: f64,
+    /// Fraction of the difference to ambient lost per tick while off
+    pub loss: f64,
+}
+
+impl Heater {
+    pub fn new(rate: f64, ambient: f64, loss: f64) -> Self {
+        Self {
+            rate,
+            ambient,
+            loss,
+        }
+    }
+}
+
+impl Default for Heater {
+    fn default() -> Self {
+        Self::new(1.0, 273.15, 0.01)
+    }
+}
+
+impl DeviceBehavior for Heater {
+    fn tick(&mut self, device: &mut DeviceType) {
+        let t = device.get_param("Temperature");
+        let t = if device.get_param("On") >= 1.0 {
+            t + self.rate
+        } else {
+            t - (t - self.ambient) * self.loss
+        };
+        device.set_param("Temperature", t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device with `params` set, ticked `ticks` times by `behavior`
+    fn ticked<B: DeviceBehavior + 'static>(
+        behavior: B,
+        params: &[(&str, f64)],
+        ticks: usize,
+    ) -> DeviceType {
+        let mut device = DeviceType::new("StructureTest");
+        for (p, v) in params {
+            device.set_param(p, *v);
+        }
+        device.set_behavior(behavior);
+        for _ in 0..ticks {
+            device.tick();
+        }
+        device
+    }
+
+    #[test]
+    fn active_vent() {
+        let outward = [("On", 1.0), ("Pressure", 0.0), ("PressureExternal", 25.0)];
+        let vent = ticked(ActiveVent::new(10.0), &outward, 2);
+        assert_eq!(vent.get_param("Pressure"), 20.0);
+        let vent = ticked(ActiveVent::new(10.0), &outward, 3);
+        assert_eq!(vent.get_param("Pressure"), 25.0);
+
+        let inward = [
+            ("On", 1.0),
+            ("Mode", 1.0),
+            ("Pressure", 25.0),
+            ("PressureExternal", 0.0),
+        ];
+        let vent = ticked(ActiveVent::new(10.0), &inward, 1);
+        assert_eq!(vent.get_param("Pressure"), 15.0);
+        // Inward never raises the pressure
+        let vent = ticked(ActiveVent::new(10.0), &[("Mode", 1.0), ("On", 1.0)], 1);
+        assert_eq!(vent.get_param("Pressure"), 0.0);
+
+        let off = [("Pressure", 0.0), ("PressureExternal", 25.0)];
+        let vent = ticked(ActiveVent::new(10.0), &off, 1);
+        assert_eq!(vent.get_param("Pressure"), 0.0);
+    }
+
+    #[test]
+    fn daylight_sensor() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-3;
+        // Ticks 0 to 3 of a 4 tick day, then tick 0 of the next. The sun is on the horizon
+        // at ticks 0 and 2, where `Activate` depends on rounding.
+        let expected = [
+            (0.0, 90.0, None),
+            (90.0, 0.0, Some(1.0)),
+            (180.0, 90.0, None),
+            (270.0, 180.0, Some(0.0)),
+            (0.0, 90.0, None),
+        ];
+        for (ticks, (horizontal, vertical, activate)) in expected.iter().enumerate() {
+            let sensor = ticked(DaylightSensor::new(4), &[], ticks + 1);
+            assert!(close(sensor.get_param("Horizontal"), *horizontal));
+            assert!(close(sensor.get_param("Vertical"), *vertical));
+            if let Some(activate) = activate {
+                assert_eq!(sensor.get_param("Activate"), *activate);
+            }
+        }
+    }
+
+    #[test]
+    fn battery() {
+        let charging = [
+            ("Maximum", 1000.0),
+            ("Charge", 900.0),
+            ("PowerInput", 100.0),
+        ];
+        let battery = ticked(Battery, &charging, 1);
+        assert_eq!(battery.get_param("Charge"), 950.0);
+        assert_eq!(battery.get_param("Ratio"), 0.95);
+        let battery = ticked(Battery, &charging, 3);
+        assert_eq!(battery.get_param("Charge"), 1000.0);
+        assert_eq!(battery.get_param("Ratio"), 1.0);
+
+        let draining = [
+            ("Maximum", 1000.0),
+            ("Charge", 50.0),
+            ("PowerOutput", 200.0),
+        ];
+        let battery = ticked(Battery, &draining, 1);
+        assert_eq!(battery.get_param("Charge"), 0.0);
+        assert_eq!(battery.get_param("Ratio"), 0.0);
+
+        let battery = ticked(Battery, &[("Charge", 50.0)], 1);
+        assert_eq!(battery.get_param("Ratio"), 0.0);
+    }
+
+    #[test]
+    fn heater() {
+        let heater = ticked(
+            Heater::new(2.0, 270.0, 0.5),
+            &[("On", 1.0), ("Temperature", 280.0)],
+            2,
+        );
+        assert_eq!(heater.get_param("Temperature"), 284.0);
+        let heater = ticked(Heater::new(2.0, 270.0, 0.5), &[("Temperature", 280.0)], 2);
+        assert_eq!(heater.get_param("Temperature"), 272.5);
+    }
+}
@@ -0,0 +1,180 @@
+/// Comparison made by conditional branch (`b*`, `br*`) and set (`s*`) instructions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Gt,
+    Le,
+    /// Approximately equal
+    Ap,
+    /// Not approximately equal
+    Na,
+    /// Device set
+    Dse,
+    /// Device not set
+    Dns,
+}
+
+impl Comparison {
+    const ALL: [(Comparison, &'static str); 10] = [
+        (Comparison::Eq, "eq"),
+        (Comparison::Ne, "ne"),
+        (Comparison::Lt, "lt"),
+        (Comparison::Ge, "ge"),
+        (Comparison::Gt, "gt"),
+        (Comparison::Le, "le"),
+        (Comparison::Ap, "ap"),
+        (Comparison::Na, "na"),
+        (Comparison::Dse, "dse"),
+        (Comparison::Dns, "dns"),
+    ];
+
+    pub fn name(&self) -> &'static str {
+        Self::ALL.iter().find(|(c, _)| c == self).unwrap().1
+    }
+
+    /// Comparison holding exactly when this one does not. `None` for the ordered
+    /// comparisons, which are all false when an operand is NaN: `bge` is not the opposite
+    /// of `blt`.
+    pub fn inverse(&self) -> Option<Self> {
+        let inverse = match self {
+            Comparison::Eq => Comparison::Ne,
+            Comparison::Ne => Comparison::Eq,
+            Comparison::Ap => Comparison::Na,
+            Comparison::Na => Comparison::Ap,
+            Comparison::Dse => Comparison::Dns,
+            Comparison::Dns => Comparison::Dse,
+            Comparison::Lt | Comparison::Ge | Comparison::Gt | Comparison::Le => return None,
+        };
+        Some(inverse)
+    }
+}
+
+/// Condition of a branch or set instruction, e.g. `lt` or, comparing against zero, `ltz`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Condition {
+    pub comparison: Comparison,
+    pub zero: bool,
+}
+
+impl Condition {
+    pub fn new(comparison: Comparison, zero: bool) -> Self {
+        Self { comparison, zero }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Comparison::ALL.iter().find_map(|(c, name)| {
+            if s == *name {
+                Some(Self::new(*c, false))
+            } else if s.strip_suffix('z') == Some(name)
+                && !matches!(c, Comparison::Dse | Comparison::Dns)
+            {
+                Some(Self::new(*c, true))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn name(&self) -> String {
+        format!(
+            "{}{}",
+            self.comparison.name(),
+            if self.zero { "z" } else { "" }
+        )
+    }
+
+    /// Condition holding exactly when this one does not, if there is one
+    pub fn inverse(&self) -> Option<Self> {
+        Some(Self::new(self.comparison.inverse()?, self.zero))
+    }
+
+    /// Number of operands tested, not counting a branch's target
+    pub fn operands(&self) -> usize {
+        let n = match self.comparison {
+            Comparison::Dse | Comparison::Dns => return 1,
+            Comparison::Ap | Comparison::Na => 3,
+            _ => 2,
+        };
+        if self.zero {
+            n - 1
+        } else {
+            n
+        }
+    }
+
+    /// Condition of a set instruction such as `slt`, `seqz` or `sdse`
+    pub fn from_set_op(op: &str) -> Option<Self> {
+        op.strip_prefix('s').and_then(Self::parse)
+    }
+
+    /// Set instruction storing whether this condition holds
+    pub fn set_op(&self) -> String {
+        format!("s{}", self.name())
+    }
+}
+
+/// Control transfer made by a jump or branch instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Branch {
+    /// `None` for unconditional jumps (`j`, `jal`, `jr`)
+    pub condition: Option<Condition>,
+    /// Target is an offset from the branch's line
+    pub relative: bool,
+    /// Stores the next line number in `ra`
+    pub link: bool,
+}
+
+impl Branch {
+    /// Classify an instruction by name, `None` if it is not a jump or branch
+    pub fn parse(op: &str) -> Option<Self> {
+        let (condition, relative, link) = match op {
+            "j" => (None, false, false),
+            "jal" => (None, false, true),
+            "jr" => (None, true, false),
+            _ => {
+                if let Some(c) = op.strip_prefix("br").and_then(Condition::parse) {
+                    (Some(c), true, false)
+                } else if let Some(c) = op
+                    .strip_prefix('b')
+                    .and_then(|s| s.strip_suffix("al"))
+                    .and_then(Condition::parse)
+                {
+                    (Some(c), false, true)
+                } else {
+                    (
+                        Some(op.strip_prefix('b').and_then(Condition::parse)?),
+                        false,
+                        false,
+                    )
+                }
+            }
+        };
+        Some(Self {
+            condition,
+            relative,
+            link,
+        })
+    }
+
+    /// Name of the instruction making this branch
+    pub fn op(&self) -> String {
+        match self.condition {
+            None if self.relative => "jr".to_owned(),
+            None if self.link => "jal".to_owned(),
+            None => "j".to_owned(),
+            Some(c) => format!(
+                "b{}{}{}",
+                if self.relative { "r" } else { "" },
+                c.name(),
+                if self.link { "al" } else { "" }
+            ),
+        }
+    }
+
+    pub fn is_conditional(&self) -> bool {
+        self.condition.is_some()
+    }
+}
@@ -0,0 +1,377 @@
+use std::{collections::BTreeSet, fmt};
+
+use petgraph::{
+    dot::Dot,
+    graph::{DiGraph, NodeIndex},
+    visit::EdgeRef,
+    Direction,
+};
+
+use crate::program::{Instruction, Program};
+
+/// Run of lines `start..end` entered only at `start` and left only after its last line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl BasicBlock {
+    pub fn lines(&self) -> std::ops::Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn last_line(&self) -> usize {
+        self.end - 1
+    }
+}
+
+impl fmt::Display for BasicBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// How control gets from one block to the next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Next line, including returning from a call made by the block
+    FallThrough,
+    /// Taken branch or jump
+    Branch,
+    /// Jump to a subroutine (`jal`, `b*al`)
+    Call,
+    /// `j ra`, to every line following a call and every label stored as a number
+    Return,
+    /// Jump to a target computed at runtime, to every block
+    Indirect,
+}
+
+impl fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Where a jump or branch goes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// Line index, at or past the end of the program for jumps ending it
+    Line(usize),
+    /// Back from a subroutine through `ra`
+    Return,
+    /// Not known before running the program
+    Indirect,
+}
+
+/// Resolve the target of the jump or branch `instr` on line `line`, `None` if it is neither
+pub fn resolve_target(program: &Program, line: usize, instr: &Instruction) -> Option<Target> {
+    let branch = instr.branch()?;
+    let token = instr.target()?;
+    if token == "ra" && !branch.relative {
+        return Some(Target::Return);
+    }
+    let value = if let Some(&l) = program.labels().get(token) {
+        l as f64
+    } else if let Some(&v) = program.defines().get(token) {
+        v
+    } else if let Ok(v) = token.parse::<f64>() {
+        v
+    } else {
+        return Some(Target::Indirect);
+    };
+    let target = if branch.relative {
+        line as f64 + value
+    } else {
+        value
+    };
+    if target < 0.0 || target.fract() != 0.0 {
+        Some(Target::Indirect)
+    } else {
+        Some(Target::Line(target as usize))
+    }
+}
+
+/// Control-flow graph of a program, one node per basic block
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    graph: DiGraph<BasicBlock, EdgeKind>,
+    /// Block of every line of the program
+    block_of_line: Vec<NodeIndex>,
+    /// Lines jumping to targets computed at runtime
+    indirect: Vec<usize>,
+}
+
+impl Cfg {
+    pub fn build(program: &Program) -> Self {
+        let targets: Vec<Option<Target>> = program
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(i, l)| {
+                l.instruction()
+                    .and_then(|instr| resolve_target(program, i, instr))
+            })
+            .collect();
+        // Where `j ra` may return to: after calls, and to labels stored as numbers
+        let labels = program.labels();
+        let mut returns = BTreeSet::new();
+        for (i, instr) in program.instructions() {
+            if instr.branch().is_some_and(|b| b.link) {
+                returns.insert(i + 1);
+            }
+            let values = match instr.branch() {
+                Some(_) => &instr.args[..instr.args.len().saturating_sub(1)],
+                None => &instr.args[..],
+            };
+            returns.extend(values.iter().filter_map(|a| labels.get(a)).cloned());
+        }
+
+        // Leaders: first line, labels, branch targets and lines following branches
+        let mut leaders = BTreeSet::new();
+        if !program.is_empty() {
+            leaders.insert(0);
+        }
+        leaders.extend(labels.values().cloned());
+        for (i, t) in targets.iter().enumerate() {
+            if let Some(t) = t {
+                leaders.insert(i + 1);
+                if let Target::Line(l) = t {
+                    leaders.insert(*l);
+                }
+            }
+        }
+        leaders.retain(|l| *l < program.len());
+
+        let mut graph = DiGraph::new();
+        let mut block_of_line = Vec::with_capacity(program.len());
+        let starts: Vec<usize> = leaders.into_iter().collect();
+        for (k, start) in starts.iter().enumerate() {
+            let end = starts.get(k + 1).cloned().unwrap_or(program.len());
+            let node = graph.add_node(BasicBlock { start: *start, end });
+            block_of_line.extend((*start..end).map(|_| node));
+        }
+
+        let mut indirect = Vec::new();
+        let nodes: Vec<NodeIndex> = graph.node_indices().collect();
+        for node in nodes.iter().cloned() {
+            let last = graph[node].last_line();
+            let next = block_of_line.get(last + 1).cloned();
+            let branch = program.lines[last]
+                .instruction()
+                .and_then(Instruction::branch);
+            let falls_through = branch.is_none_or(|b| b.is_conditional() || b.link);
+            if let Some(next) = next.filter(|_| falls_through) {
+                graph.add_edge(node, next, EdgeKind::FallThrough);
+            }
+            let kind = match branch {
+                Some(b) if b.link => EdgeKind::Call,
+                Some(_) => EdgeKind::Branch,
+                None => continue,
+            };
+            match targets[last] {
+                Some(Target::Line(l)) => {
+                    if let Some(to) = block_of_line.get(l) {
+                        graph.add_edge(node, *to, kind);
+                    }
+                }
+                Some(Target::Return) => {
+                    for to in returns.iter() {
+                        if let Some(to) = block_of_line.get(*to) {
+                            graph.add_edge(node, *to, EdgeKind::Return);
+                        }
+                    }
+                }
+                Some(Target::Indirect) | None => {
+                    indirect.push(last);
+                    for to in nodes.iter() {
+                        graph.add_edge(node, *to, EdgeKind::Indirect);
+                    }
+                }
+            }
+        }
+
+        Self {
+            graph,
+            block_of_line,
+            indirect,
+        }
+    }
+
+    pub fn graph(&self) -> &DiGraph<BasicBlock, EdgeKind> {
+        &self.graph
+    }
+
+    /// Block holding the first line, `None` for an empty program
+    pub fn entry(&self) -> Option<NodeIndex> {
+        self.block_of_line.first().cloned()
+    }
+
+    pub fn block(&self, node: NodeIndex) -> BasicBlock {
+        self.graph[node]
+    }
+
+    pub fn block_of(&self, line: usize) -> Option<NodeIndex> {
+        self.block_of_line.get(line).cloned()
+    }
+
+    /// Blocks in program order
+    pub fn blocks(&self) -> impl Iterator<Item = (NodeIndex, BasicBlock)> + '_ {
+        self.graph.node_indices().map(move |n| (n, self.graph[n]))
+    }
+
+    pub fn successors(&self, node: NodeIndex) -> Vec<(NodeIndex, EdgeKind)> {
+        self.edges(node, Direction::Outgoing)
+    }
+
+    pub fn predecessors(&self, node: NodeIndex) -> Vec<(NodeIndex, EdgeKind)> {
+        self.edges(node, Direction::Incoming)
+    }
+
+    fn edges(&self, node: NodeIndex, dir: Direction) -> Vec<(NodeIndex, EdgeKind)> {
+        let mut edges: Vec<_> = self
+            .graph
+            .edges_directed(node, dir)
+            .map(|e| {
+                let other = if dir == Direction::Outgoing {
+                    e.target()
+                } else {
+                    e.source()
+                };
+                (other, *e.weight())
+            })
+            .collect();
+        edges.sort_by_key(|(n, _)| *n);
+        edges
+    }
+
+    /// Lines jumping to targets computed at runtime
+    pub fn indirect_lines(&self) -> &[usize] {
+        &self.indirect
+    }
+
+    pub fn has_indirect_jumps(&self) -> bool {
+        !self.indirect.is_empty()
+    }
+
+    /// Graphviz rendering, blocks labelled with their line ranges
+    pub fn to_dot(&self) -> String {
+        format!("{}", Dot::with_config(&self.graph, &[]))
+    }
+}
+
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (node, block) in self.blocks() {
+            write!(f, "block {} [{}]", node.index(), block)?;
+            for (to, kind) in self.successors(node) {
+                write!(f, " {}:{}", kind, to.index())?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Blocks of `lines` and their successors, as displayed
+    fn cfg(lines: &[&str]) -> String {
+        Cfg::build(&Program::parse(lines)).to_string()
+    }
+
+    #[test]
+    fn no_edges_past_the_end() {
+        assert_eq!(cfg(&["move r0 1", "yield"]), "block 0 [0..2]\n");
+        assert_eq!(
+            cfg(&["loop:", "add r0 r0 1", "bgtz r0 loop"]),
+            "block 0 [0..3] Branch:0\n"
+        );
+        assert_eq!(cfg(&[]), "");
+    }
+
+    #[test]
+    fn branches_fall_through_jumps_do_not() {
+        let lines = [
+            "beqz r0 skip",
+            "move r0 1",
+            "j end",
+            "skip:",
+            "move r0 2",
+            "end:",
+        ];
+        assert_eq!(
+            cfg(&lines),
+            "block 0 [0..1] FallThrough:1 Branch:2\n\
+             block 1 [1..3] Branch:3\n\
+             block 2 [3..5] FallThrough:3\n\
+             block 3 [5..6]\n"
+        );
+    }
+
+    #[test]
+    fn relative_branches() {
+        let lines = ["move r0 1", "brgtz r0 2", "move r0 2", "yield", "jr -3"];
+        assert_eq!(
+            cfg(&lines),
+            "block 0 [0..1] FallThrough:1\n\
+             block 1 [1..2] FallThrough:2 Branch:3\n\
+             block 2 [2..3] FallThrough:3\n\
+             block 3 [3..5] Branch:1\n"
+        );
+        // Before the first line, not known until run
+        let program = Program::parse(&["yield", "jr -2"]);
+        let cfg = Cfg::build(&program);
+        assert_eq!(cfg.indirect_lines(), [1]);
+    }
+
+    #[test]
+    fn calls_return_after_every_call() {
+        let lines = [
+            "jal f",
+            "s d0 On 1",
+            "jal f",
+            "j end",
+            "f:",
+            "add r0 r0 1",
+            "j ra",
+            "end:",
+        ];
+        assert_eq!(
+            cfg(&lines),
+            "block 0 [0..1] FallThrough:1 Call:3\n\
+             block 1 [1..3] FallThrough:2 Call:3\n\
+             block 2 [3..4] Branch:4\n\
+             block 3 [4..7] Return:1 Return:2\n\
+             block 4 [7..8]\n"
+        );
+    }
+
+    #[test]
+    fn returns_to_labels_stored_as_numbers() {
+        let lines = ["move ra back", "j f", "back:", "yield", "f:", "j ra"];
+        assert_eq!(
+            cfg(&lines),
+            "block 0 [0..2] Branch:2\n\
+             block 1 [2..4] FallThrough:2\n\
+             block 2 [4..6] Return:1\n"
+        );
+    }
+
+    #[test]
+    fn indirect_jumps_reach_every_block() {
+        let lines = ["l r0 d0 Setting", "j r0", "yield", "j 0"];
+        let program = Program::parse(&lines);
+        let cfg = Cfg::build(&program);
+        assert_eq!(
+            cfg.to_string(),
+            "block 0 [0..2] Indirect:0 Indirect:1\n\
+             block 1 [2..4] Branch:0\n"
+        );
+        assert_eq!(cfg.indirect_lines(), [1]);
+        assert!(cfg.has_indirect_jumps());
+        assert!(!Cfg::build(&Program::parse(&["j 0"])).has_indirect_jumps());
+    }
+}
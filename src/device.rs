@@ -1,23 +1,117 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{behavior::DeviceBehavior, hash::hash};
+
+/// Shared handle to a device, so that several ICs and networks can reference it
+pub type DeviceRef = Rc<RefCell<DeviceType>>;
 
 #[derive(Clone, Debug)]
 pub struct DeviceType {
     name: String,
-    parameters: HashMap<String, f32>,
+    label: Option<String>,
+    parameters: HashMap<String, f64>,
+    slots: Vec<HashMap<String, f64>>,
+    behavior: Option<Box<dyn DeviceBehavior>>,
 }
 
 impl DeviceType {
-    pub fn get_param(&self, p: &str) -> f32 {
+    /// New device of prefab `name`, with its `PrefabHash` parameter set
+    pub fn new(name: &str) -> Self {
+        let mut dt = Self {
+            name: name.to_owned(),
+            label: None,
+            parameters: HashMap::new(),
+            slots: Vec::new(),
+            behavior: None,
+        };
+        dt.set_param("PrefabHash", hash(name) as f64);
+        dt
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Label given to the device, defaults to its name
+    pub fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn set_label(&mut self, label: &str) {
+        self.label = Some(label.to_owned());
+    }
+
+    /// Hash of the device's prefab, as matched by batch instructions
+    pub fn prefab_hash(&self) -> i32 {
+        hash(&self.name)
+    }
+
+    /// Hash of the device's label, as matched by `lbn`/`sbn`
+    pub fn name_hash(&self) -> i32 {
+        hash(self.label())
+    }
+
+    pub fn get_param(&self, p: &str) -> f64 {
         *self.parameters.get(p).unwrap_or(&0.0)
     }
 
-    pub fn set_param(&mut self, p: &str, v: f32) {
+    pub fn set_param(&mut self, p: &str, v: f64) {
         self.parameters.insert(p.to_owned(), v);
     }
+
+    pub fn get_slot_param(&self, slot: usize, p: &str) -> Result<f64, String> {
+        match self.slots.get(slot) {
+            Some(params) => Ok(*params.get(p).unwrap_or(&0.0)),
+            None => Err(format!(
+                "Invalid slot index '{}' for '{}'",
+                slot,
+                self.label()
+            )),
+        }
+    }
+
+    /// Set a parameter of slot `slot`, adding slots up to it as needed
+    pub fn set_slot_param(&mut self, slot: usize, p: &str, v: f64) {
+        if self.slots.len() <= slot {
+            self.slots.resize(slot + 1, HashMap::new());
+        }
+        self.slots[slot].insert(p.to_owned(), v);
+    }
+
+    pub fn set_behavior<B: DeviceBehavior + 'static>(&mut self, b: B) {
+        self.behavior = Some(Box::new(b));
+    }
+
+    /// Advance this device's behaviour (if any) by one tick
+    pub fn tick(&mut self) {
+        if let Some(mut b) = self.behavior.take() {
+            b.tick(self);
+            self.behavior = Some(b);
+        }
+    }
+}
+
+impl From<DeviceType> for DeviceRef {
+    fn from(dt: DeviceType) -> Self {
+        Rc::new(RefCell::new(dt))
+    }
+}
+
+/// Advance every distinct device in `devices` by one tick.
+///
+/// Devices referenced more than once (e.g. shared between ICs) are only ticked once.
+pub fn tick_devices<'a>(devices: impl Iterator<Item = &'a DeviceRef>) {
+    let mut seen: Vec<*const RefCell<DeviceType>> = Vec::new();
+    for d in devices {
+        if !seen.contains(&Rc::as_ptr(d)) {
+            seen.push(Rc::as_ptr(d));
+            d.borrow_mut().tick();
+        }
+    }
 }
 
 #[derive(Clone)]
 pub enum Device {
     Unset,
-    Set(DeviceType),
+    Set(DeviceRef),
 }
@@ -0,0 +1,17 @@
+/// Stationeers hash of a string (prefab names, labels), as used by `HASH("...")`.
+///
+/// This is the signed CRC-32 (IEEE) of the string's bytes.
+pub fn hash(s: &str) -> i32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for b in s.bytes() {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc as i32
+}
@@ -4,17 +4,27 @@ use std::collections::HashMap;
 
 use crate::{
     alias::Alias,
-    device::{Device, DeviceType},
+    device::{Device, DeviceRef, DeviceType},
+    hash::hash,
+    network::{BatchMode, NetworkRef},
+    profile::HardwareProfile,
 };
 
+/// Prefab of the housing every IC is mounted in
+pub const HOUSING_PREFAB: &str = "StructureCircuitHousing";
+
 pub struct ICState {
     // Hard state
+    // Pins followed by the housing (`db`)
     devices: Vec<Device>,
-    registers: Vec<f32>,
+    registers: Vec<f64>,
     aliases: HashMap<String, Alias>,
-    definitions: HashMap<String, f32>,
+    definitions: HashMap<String, f64>,
     labels: HashMap<String, usize>,
-    stack: Vec<f32>,
+    stack: Vec<f64>,
+    // Network the housing is connected to
+    network: Option<NetworkRef>,
+    profile: HardwareProfile,
     // Operation state
     pub instr_per_tick: usize,
     pub instr_counter: usize,
@@ -25,49 +35,120 @@ pub struct ICState {
 // Most public functions for ICState are helper functions for writing
 // instructions sets.
 impl ICState {
+    /// IC with the given hardware and no program size limits
     pub fn new(
         ndevices: usize,
         nregisters: usize,
         stack_size: usize,
         instr_per_tick: usize,
     ) -> Self {
-        let daliases = (0..ndevices).map(|i| (format!("d{}", i), Alias::Device(i, true)));
+        Self::from_profile(HardwareProfile::custom(
+            ndevices,
+            nregisters,
+            stack_size,
+            instr_per_tick,
+        ))
+    }
+
+    pub fn from_profile(profile: HardwareProfile) -> Self {
+        let ndevices = profile.pins;
+        let nregisters = profile.registers;
+        let daliases = (0..ndevices)
+            .map(|i| (format!("d{}", i), Alias::Device(i, true)))
+            .chain(std::iter::once((
+                "db".to_owned(),
+                Alias::Device(ndevices, true),
+            )));
         let raliases = (0..nregisters)
             .map(|i| (format!("r{}", i), Alias::Register(i, true)))
             .chain(
                 ["ra", "sp"]
                     .iter()
                     .enumerate()
-                    .map(|(i, &l)| (l.to_owned(), Alias::Register(i + nregisters + 1, true))),
+                    .map(|(i, &l)| (l.to_owned(), Alias::Register(i + nregisters, true))),
             );
+        let mut housing = DeviceType::new(HOUSING_PREFAB);
+        housing.set_param("On", 1.0);
+        let mut devices = vec![Device::Unset; ndevices];
+        devices.push(Device::Set(housing.into()));
         Self {
-            devices: vec![Device::Unset; ndevices],
+            devices,
             registers: vec![0.0; nregisters + 2],
             aliases: daliases.chain(raliases).collect(),
             definitions: HashMap::new(),
             labels: HashMap::new(),
-            stack: vec![0.0; stack_size],
+            stack: vec![0.0; profile.stack_size],
+            network: None,
             next_line: 0,
-            instr_per_tick,
+            instr_per_tick: profile.instr_per_tick,
+            profile,
             instr_counter: 0,
             halt: false,
         }
     }
 
-    pub fn get_ra(&self) -> f32 {
+    pub fn profile(&self) -> &HardwareProfile {
+        &self.profile
+    }
+
+    /// Reset the per-tick operation state before running a new tick
+    pub fn begin_tick(&mut self) {
+        self.instr_counter = 0;
+        self.halt = false;
+    }
+
+    /// The housing this IC is mounted in, addressable as `db`
+    pub fn housing(&self) -> &DeviceRef {
+        match self.devices.last() {
+            Some(Device::Set(d)) => d,
+            _ => unreachable!("IC without housing"),
+        }
+    }
+
+    /// Whether the housing is switched on, an IC in a switched off housing does not run
+    pub fn is_on(&self) -> bool {
+        self.housing().borrow().get_param("On") >= 1.0
+    }
+
+    /// Whether the IC stopped on a runtime error (the housing's `Error`)
+    pub fn has_error(&self) -> bool {
+        self.housing().borrow().get_param("Error") >= 1.0
+    }
+
+    pub fn set_error(&mut self, error: bool) {
+        let v = if error { 1.0 } else { 0.0 };
+        self.housing().borrow_mut().set_param("Error", v);
+    }
+
+    /// Expose the line about to be run as the housing's `LineNumber`
+    pub fn set_line_number(&mut self, l: usize) {
+        self.housing()
+            .borrow_mut()
+            .set_param("LineNumber", l as f64);
+    }
+
+    /// Devices mounted on this IC's pins and its housing
+    pub fn devices(&self) -> impl Iterator<Item = &DeviceRef> {
+        self.devices.iter().filter_map(|d| match d {
+            Device::Set(d) => Some(d),
+            Device::Unset => None,
+        })
+    }
+
+    pub fn get_ra(&self) -> f64 {
         self.registers[self.registers.len() - 2]
     }
 
-    pub fn set_ra(&mut self, v: f32) {
+    pub fn set_ra(&mut self, v: f64) {
         let i = self.registers.len() - 2;
         self.registers[i] = v;
     }
 
-    pub fn get_sp(&self) -> f32 {
+    pub fn get_sp(&self) -> f64 {
         self.registers[self.registers.len() - 1]
     }
 
-    pub fn set_sp(&mut self, v: f32) {
+    pub fn set_sp(&mut self, v: f64) {
         let i = self.registers.len() - 1;
         self.registers[i] = v;
     }
@@ -79,21 +160,20 @@ impl ICState {
     /// * `relative` - If false, next line number is set to `l` absolute,
     ///   else `l` is added to the current line number.
     /// * `save` - If true, register `ra` is assigned the next line number
-    pub fn branch_helper(&mut self, l: usize, f: bool, relative: bool, save: bool) {
-        let l = l as f32;
+    pub fn branch_helper(&mut self, l: f64, f: bool, relative: bool, save: bool) {
         if save {
-            self.set_ra(self.next_line as f32);
+            self.set_ra(self.next_line as f64);
         }
         if f {
             self.next_line = if relative {
-                l - 1.0 + self.next_line as f32
+                l - 1.0 + self.next_line as f64
             } else {
                 l
             } as usize;
         }
     }
 
-    pub fn set_register(&mut self, r: Alias, v: f32) -> Result<(), String> {
+    pub fn set_register(&mut self, r: Alias, v: f64) -> Result<(), String> {
         if let Some(r) = {
             if let Alias::Register(i, _) = r {
                 self.registers.get_mut(i)
@@ -108,6 +188,17 @@ impl ICState {
         }
     }
 
+    pub fn get_register(&self, r: Alias) -> Result<f64, String> {
+        match r {
+            Alias::Register(i, _) => self
+                .registers
+                .get(i)
+                .copied()
+                .ok_or_else(|| "Invalid register index".to_owned()),
+            _ => Err(format!("'{:?}' not a register alias", r)),
+        }
+    }
+
     pub fn get_device(&mut self, d: Alias) -> Result<&Device, String> {
         if let Alias::Device(i, _) = d {
             if let Some(d) = self.devices.get(i) {
@@ -132,7 +223,7 @@ impl ICState {
         self.aliases.insert(t.to_owned(), a);
     }
 
-    pub fn add_definition(&mut self, t: &str, n: f32) {
+    pub fn add_definition(&mut self, t: &str, n: f64) {
         self.definitions.insert(t.to_owned(), n);
     }
 
@@ -161,90 +252,206 @@ impl ICState {
         }
     }
 
-    pub fn try_number(&self, token: &str) -> Result<f32, String> {
+    pub fn try_number(&self, token: &str) -> Result<f64, String> {
         if let Some(Alias::Register(i, _)) = self.aliases.get(token) {
             Ok(self.registers[*i])
-        } else if let Ok(n) = token.parse::<f32>() {
+        } else if let Ok(n) = token.parse::<f64>() {
             Ok(n)
         } else if let Some(n) = self.definitions.get(token) {
             Ok(*n)
+        } else if let Some(n) = self.labels.get(token) {
+            Ok(*n as f64)
+        } else if let Some(s) = token
+            .strip_prefix("HASH(\"")
+            .and_then(|t| t.strip_suffix("\")"))
+        {
+            Ok(hash(s) as f64)
         } else {
             Err("Not a number!".to_owned())
         }
     }
 
-    pub fn try_line_number(&self, token: &str) -> Result<usize, String> {
+    /// Absolute line number or relative offset, which may be negative
+    pub fn try_line_number(&self, token: &str) -> Result<f64, String> {
         if let Some(Alias::Register(i, _)) = self.aliases.get(token) {
-            Ok(self.registers[*i] as usize)
-        } else if let Ok(n) = token.parse::<f32>() {
-            Ok(n as usize)
+            Ok(self.registers[*i])
+        } else if let Ok(n) = token.parse::<f64>() {
+            Ok(n)
         } else if let Some(n) = self.labels.get(token) {
-            Ok(*n)
+            Ok(*n as f64)
         } else {
             Err("Not a line number!".to_owned())
         }
     }
 
     pub fn try_set_device(&mut self, a: Alias, dt: DeviceType) -> Result<(), String> {
-        if let Some(d) = self.devices.get_mut(a.device_index()?) {
-            *d = Device::Set(dt);
+        self.try_mount_device(a, dt.into())
+    }
+
+    /// Mount a (possibly shared) device on a pin
+    pub fn try_mount_device(&mut self, a: Alias, d: DeviceRef) -> Result<(), String> {
+        let npins = self.devices.len() - 1;
+        if let Some(p) = self.devices[..npins].get_mut(a.device_index()?) {
+            *p = Device::Set(d);
             Ok(())
         } else {
             Err(format!("Invalid device alias '{:?}'", a))
         }
     }
 
-    pub fn try_get_device_param(&self, a: Alias, p: &str) -> Result<f32, String> {
+    pub fn try_get_device_param(&self, a: Alias, p: &str) -> Result<f64, String> {
         if let Some(Device::Set(dt)) = self.devices.get(a.device_index()?) {
-            Ok(dt.get_param(p))
+            Ok(dt.borrow().get_param(p))
         } else {
             Err(format!("Invalid device alias '{:?}'", a))
         }
     }
 
-    pub fn try_set_device_param(&mut self, a: Alias, p: &str, v: f32) -> Result<(), String> {
-        if let Some(Device::Set(dt)) = self.devices.get_mut(a.device_index()?) {
-            dt.set_param(p, v);
+    pub fn try_set_device_param(&mut self, a: Alias, p: &str, v: f64) -> Result<(), String> {
+        if let Some(Device::Set(dt)) = self.devices.get(a.device_index()?) {
+            dt.borrow_mut().set_param(p, v);
             Ok(())
         } else {
             Err(format!("Invalid device alias '{:?}'", a))
         }
     }
 
-    fn validate_sp(&self) -> bool {
-        let i = self.get_sp(); // TODO: validate mantisa
-        i < 0.0 || i >= self.stack.len() as f32
+    /// Connect the IC to the network its housing is on, for reference id and batch access
+    pub fn set_network(&mut self, network: NetworkRef) {
+        self.network = Some(network);
     }
 
-    pub fn try_peek(&self) -> Result<f32, String> {
-        let i = self.get_sp();
-        if self.validate_sp() {
-            Err(format!("Stack index '{}' out of pop range", i))
-        } else {
-            Ok(self.stack[i as usize])
+    fn try_network(&self) -> Result<&NetworkRef, String> {
+        self.network
+            .as_ref()
+            .ok_or_else(|| "IC is not connected to a network".to_owned())
+    }
+
+    /// Device on the IC's network with reference id `id`
+    fn try_network_device(&self, id: f64) -> Result<DeviceRef, String> {
+        if id.is_nan() || id < 0.0 {
+            return Err(format!("Invalid reference id '{}'", id));
         }
+        self.try_network()?
+            .borrow()
+            .get_device(id as usize)
+            .cloned()
+            .ok_or_else(|| format!("No device with reference id '{}'", id))
     }
 
-    pub fn try_pop(&mut self) -> Result<f32, String> {
-        let i = self.get_sp();
-        if self.validate_sp() {
-            Err(format!("Stack index '{}' out of pop range", i))
+    pub fn try_get_network_param(&self, id: f64, p: &str) -> Result<f64, String> {
+        Ok(self.try_network_device(id)?.borrow().get_param(p))
+    }
+
+    pub fn try_set_network_param(&mut self, id: f64, p: &str, v: f64) -> Result<(), String> {
+        self.try_network_device(id)?.borrow_mut().set_param(p, v);
+        Ok(())
+    }
+
+    pub fn try_get_device_slot_param(&self, a: Alias, s: f64, p: &str) -> Result<f64, String> {
+        if let Some(Device::Set(dt)) = self.devices.get(a.device_index()?) {
+            dt.borrow().get_slot_param(s as usize, p)
         } else {
-            self.set_sp(i - 1.0);
-            Ok(self.stack[i as usize])
+            Err(format!("Invalid device alias '{:?}'", a))
+        }
+    }
+
+    /// Load `p` (or slot `slot`'s `p`) from every device on the network with prefab hash
+    /// `h` and, if given, name hash `n`, combined according to batch mode `mode`.
+    pub fn try_batch_load(
+        &self,
+        h: f64,
+        n: Option<f64>,
+        slot: Option<f64>,
+        p: &str,
+        mode: &str,
+    ) -> Result<f64, String> {
+        let mode = BatchMode::parse(mode)?;
+        let (h, n) = (try_hash(h)?, n.map(try_hash).transpose()?);
+        let devices = self.try_network()?.borrow().find_devices(h, n);
+        let values: Vec<f64> = devices
+            .iter()
+            .map(|d| match slot {
+                Some(s) => d.borrow().get_slot_param(s as usize, p),
+                None => Ok(d.borrow().get_param(p)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(mode.apply(&values))
+    }
+
+    /// Store `v` to `p` (or slot `slot`'s `p`) of every device on the network with prefab
+    /// hash `h` and, if given, name hash `n`.
+    pub fn try_batch_store(
+        &mut self,
+        h: f64,
+        n: Option<f64>,
+        slot: Option<f64>,
+        p: &str,
+        v: f64,
+    ) -> Result<(), String> {
+        let (h, n) = (try_hash(h)?, n.map(try_hash).transpose()?);
+        let devices = self.try_network()?.borrow().find_devices(h, n);
+        for d in devices {
+            match slot {
+                Some(s) => d.borrow_mut().set_slot_param(s as usize, p, v),
+                None => d.borrow_mut().set_param(p, v),
+            }
         }
+        Ok(())
     }
 
-    pub fn try_push(&mut self, n: f32) -> Result<(), String> {
-        let i = self.get_sp(); // TODO: validate mantisa
-        if self.validate_sp() {
-            Err(format!("Stack index '{}' out of pop range", i))
+    /// Index into the stack for address `i`
+    fn try_stack_index(&self, i: f64) -> Result<usize, String> {
+        // TODO: validate mantisa
+        if !(0.0..self.stack.len() as f64).contains(&i) {
+            Err(format!("Stack index '{}' out of range", i))
         } else {
-            self.set_sp(i - 1.0);
-            self.stack[i as usize] = n;
+            Ok(i as usize)
+        }
+    }
+
+    /// Value on top of the stack (at `sp - 1`)
+    pub fn try_peek(&self) -> Result<f64, String> {
+        let i = self.try_stack_index(self.get_sp() - 1.0)?;
+        Ok(self.stack[i])
+    }
+
+    pub fn try_pop(&mut self) -> Result<f64, String> {
+        let i = self.try_stack_index(self.get_sp() - 1.0)?;
+        self.set_sp(i as f64);
+        Ok(self.stack[i])
+    }
+
+    /// Store `n` at `sp`, then increment `sp`
+    pub fn try_push(&mut self, n: f64) -> Result<(), String> {
+        let i = self.try_stack_index(self.get_sp())?;
+        self.stack[i] = n;
+        self.set_sp(i as f64 + 1.0);
+        Ok(())
+    }
+
+    /// Check `a` is a device with stack memory, only the IC's own housing has one
+    fn try_stack_device(&self, a: Alias) -> Result<(), String> {
+        if a.device_index()? == self.devices.len() - 1 {
             Ok(())
+        } else {
+            Err(format!("Device '{:?}' has no stack memory", a))
         }
     }
+
+    /// Value at `address` of the stack memory of device `a`
+    pub fn try_get_stack(&self, a: Alias, address: f64) -> Result<f64, String> {
+        self.try_stack_device(a)?;
+        Ok(self.stack[self.try_stack_index(address)?])
+    }
+
+    /// Store `v` at `address` of the stack memory of device `a`
+    pub fn try_put_stack(&mut self, a: Alias, address: f64, v: f64) -> Result<(), String> {
+        self.try_stack_device(a)?;
+        let i = self.try_stack_index(address)?;
+        self.stack[i] = v;
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for ICState {
@@ -278,6 +485,178 @@ impl std::fmt::Display for ICState {
 
 impl Default for ICState {
     fn default() -> Self {
-        Self::new(6, 16, 512, 128)
+        Self::from_profile(HardwareProfile::ic10())
+    }
+}
+
+/// Prefab or name hash given as a number
+fn try_hash(h: f64) -> Result<i32, String> {
+    if h.fract() == 0.0 && (i32::MIN as f64..=i32::MAX as f64).contains(&h) {
+        Ok(h as i32)
+    } else {
+        Err(format!("Invalid hash '{}'", h))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{instruction::StationeersInstructionSet, network::Network, try_run_tick};
+
+    fn run_tick(ic: &mut ICState, lines: &[&str]) -> Result<(), String> {
+        let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        try_run_tick(ic, &lines, &StationeersInstructionSet::new())
+    }
+
+    fn housing_param(ic: &ICState, p: &str) -> f64 {
+        ic.housing().borrow().get_param(p)
+    }
+
+    #[test]
+    fn housing_is_db() {
+        let mut ic = ICState::default();
+        run_tick(&mut ic, &["s db Setting 5", "l r0 db PrefabHash"]).unwrap();
+        assert_eq!(housing_param(&ic, "Setting"), 5.0);
+        assert_eq!(
+            ic.get_register(ic.try_register("r0").unwrap()),
+            Ok(hash(HOUSING_PREFAB) as f64)
+        );
+    }
+
+    #[test]
+    fn switched_off_housings_pause() {
+        let mut ic = ICState::default();
+        let lines = ["add r0 r0 1", "yield", "j 0"];
+        ic.housing().borrow_mut().set_param("On", 0.0);
+        run_tick(&mut ic, &lines).unwrap();
+        let r0 = ic.try_register("r0").unwrap();
+        assert_eq!(ic.get_register(r0), Ok(0.0));
+        ic.housing().borrow_mut().set_param("On", 1.0);
+        run_tick(&mut ic, &lines).unwrap();
+        run_tick(&mut ic, &lines).unwrap();
+        assert_eq!(ic.get_register(r0), Ok(2.0));
+    }
+
+    #[test]
+    fn line_number_is_the_next_line() {
+        let mut ic = ICState::default();
+        let lines = ["move r0 1", "yield", "move r0 2"];
+        run_tick(&mut ic, &lines).unwrap();
+        assert_eq!(housing_param(&ic, "LineNumber"), 2.0);
+        run_tick(&mut ic, &lines).unwrap();
+        assert_eq!(housing_param(&ic, "LineNumber"), 3.0);
+    }
+
+    #[test]
+    fn runtime_errors_raise_error_and_stop() {
+        let mut ic = ICState::default();
+        let lines = ["add r0 r0 1", "s d5 On 1"];
+        assert!(run_tick(&mut ic, &lines).is_err());
+        assert_eq!(housing_param(&ic, "Error"), 1.0);
+        assert_eq!(housing_param(&ic, "LineNumber"), 1.0);
+        ic.next_line = 0;
+        run_tick(&mut ic, &lines).unwrap();
+        assert_eq!(ic.get_register(ic.try_register("r0").unwrap()), Ok(1.0));
+    }
+
+    #[test]
+    fn nan_stack_indices_fail() {
+        let mut ic = ICState::default();
+        ic.try_push(1.0).unwrap();
+        ic.set_sp(f64::NAN);
+        assert!(ic.try_peek().is_err());
+        assert!(ic.try_pop().is_err());
+    }
+
+    #[test]
+    fn negative_reference_ids_fail() {
+        let mut ic = ICState::default();
+        let mut network = Network::new("base");
+        network.add_device(0, DeviceType::new("StructureLogicMemory"));
+        ic.set_network(Rc::new(RefCell::new(network)));
+        assert_eq!(ic.try_get_network_param(0.0, "On"), Ok(0.0));
+        assert!(ic.try_get_network_param(-1.0, "On").is_err());
+        assert!(ic.try_get_network_param(f64::NAN, "On").is_err());
+        assert!(ic.try_set_network_param(-1.0, "On", 1.0).is_err());
+    }
+
+    /// IC connected to a network of two lights and a sensor with reference id 100
+    fn networked() -> ICState {
+        let mut ic = ICState::default();
+        let mut network = Network::new("base");
+        for (id, prefab, label, setting) in [
+            (1, "StructureWallLight", "Hallway", 1.0),
+            (2, "StructureWallLight", "Kitchen", 4.0),
+            (100, "StructureGasSensor", "Sensor", 293.15),
+        ] {
+            let mut dt = DeviceType::new(prefab);
+            dt.set_label(label);
+            dt.set_param("Setting", setting);
+            dt.set_slot_param(0, "Quantity", setting);
+            network.add_device(id, dt);
+        }
+        ic.set_network(Rc::new(RefCell::new(network)));
+        ic
+    }
+
+    fn registers(ic: &ICState, names: &[&str]) -> Vec<f64> {
+        names
+            .iter()
+            .map(|r| ic.get_register(ic.try_register(r).unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn batch_loads() {
+        let mut ic = networked();
+        let lines = [
+            r#"define LIGHT HASH("StructureWallLight")"#,
+            "lb r0 LIGHT Setting Average",
+            "lb r1 LIGHT Setting Sum",
+            "lb r2 LIGHT Setting Minimum",
+            "lb r3 LIGHT Setting 3",
+            r#"lbn r4 LIGHT HASH("Kitchen") Setting Sum"#,
+            "lbs r5 LIGHT 0 Quantity Sum",
+            r#"lbns r6 LIGHT HASH("Hallway") 0 Quantity Maximum"#,
+            r#"lb r7 HASH("StructureLogicMemory") Setting Maximum"#,
+            r#"lbn r8 LIGHT HASH("Garage") Setting Sum"#,
+        ];
+        run_tick(&mut ic, &lines).unwrap();
+        let names = ["r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8"];
+        assert_eq!(
+            registers(&ic, &names),
+            [2.5, 5.0, 1.0, 4.0, 4.0, 5.0, 1.0, 0.0, 0.0]
+        );
+        assert!(run_tick(&mut networked(), &["lb r0 1 Setting Total"]).is_err());
+        assert!(run_tick(&mut networked(), &["lb r0 0.5 Setting Sum"]).is_err());
+    }
+
+    #[test]
+    fn batch_stores() {
+        let mut ic = networked();
+        let lines = [
+            r#"define LIGHT HASH("StructureWallLight")"#,
+            "sb LIGHT On 1",
+            r#"sbn LIGHT HASH("Kitchen") On 0"#,
+            "sbs LIGHT 1 Quantity 7",
+            r#"sb HASH("StructureLogicMemory") On 1"#,
+            "lb r0 LIGHT On Sum",
+            "lbs r1 LIGHT 1 Quantity Sum",
+            "ld r2 100 On",
+        ];
+        run_tick(&mut ic, &lines).unwrap();
+        assert_eq!(registers(&ic, &["r0", "r1", "r2"]), [1.0, 14.0, 0.0]);
+    }
+
+    #[test]
+    fn loads_and_stores_by_reference_id() {
+        let mut ic = networked();
+        let lines = ["ld r0 100 Setting", "sd 2 Setting 9", "ld r1 2 Setting"];
+        run_tick(&mut ic, &lines).unwrap();
+        assert_eq!(registers(&ic, &["r0", "r1"]), [293.15, 9.0]);
+        assert!(run_tick(&mut networked(), &["ld r0 3 Setting"]).is_err());
+        assert!(run_tick(&mut ICState::default(), &["ld r0 1 Setting"]).is_err());
     }
 }
@@ -0,0 +1,211 @@
+use std::{collections::BTreeMap, fmt};
+
+use itertools::Itertools;
+
+use crate::{
+    analysis::{Aliases, Effects, Liveness, Register, RegisterSet},
+    cfg::Cfg,
+    program::Program,
+};
+
+/// Lines `start..=end` over which a register holds a value that is still to be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiveRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl fmt::Display for LiveRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Live ranges of every register of a program, from its liveness on the control-flow graph.
+///
+/// A register occupies a line if it is live before or after it, or written by it.
+#[derive(Clone, Debug)]
+pub struct Lifetimes {
+    lines: Vec<String>,
+    /// Registers occupied on every line
+    occupied: Vec<RegisterSet>,
+    /// Registers written on every line
+    defs: Vec<RegisterSet>,
+    ranges: BTreeMap<Register, Vec<LiveRange>>,
+    /// Names of the registers
+    aliases: Aliases,
+}
+
+impl Lifetimes {
+    pub fn compute(program: &Program) -> Self {
+        let liveness = Liveness::compute(program, &Cfg::build(program));
+        let aliases = Aliases::of(program);
+        let defs: Vec<RegisterSet> = program
+            .lines
+            .iter()
+            .map(|l| {
+                l.instruction()
+                    .map(|i| Effects::of(i, &aliases).may_defs)
+                    .unwrap_or_default()
+            })
+            .collect();
+        let occupied: Vec<RegisterSet> = (0..program.len())
+            .map(|i| {
+                let live = liveness.live_in[i].union(&liveness.live_out[i]).cloned();
+                live.chain(defs[i].iter().cloned()).collect()
+            })
+            .collect();
+
+        let mut ranges: BTreeMap<Register, Vec<LiveRange>> = BTreeMap::new();
+        for (i, regs) in occupied.iter().enumerate() {
+            for r in regs.iter() {
+                let r_ranges = ranges.entry(*r).or_default();
+                match r_ranges.last_mut() {
+                    Some(range) if range.end + 1 == i => range.end = i,
+                    _ => r_ranges.push(LiveRange { start: i, end: i }),
+                }
+            }
+        }
+        Self {
+            lines: program.to_lines(),
+            occupied,
+            defs,
+            ranges,
+            aliases,
+        }
+    }
+
+    pub fn ranges(&self) -> &BTreeMap<Register, Vec<LiveRange>> {
+        &self.ranges
+    }
+
+    /// Registers occupied on line `line`
+    pub fn occupied(&self, line: usize) -> Option<&RegisterSet> {
+        self.occupied.get(line)
+    }
+
+    /// Most registers occupied on a single line
+    pub fn pressure(&self) -> usize {
+        self.occupied.iter().map(|r| r.len()).max().unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Lifetimes {
+    /// Live ranges per register, then a chart of them along the program:
+    /// `*` where a register is written, `|` where it is live
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> = self.ranges.keys().map(|r| self.aliases.name(*r)).collect();
+        let width = names.iter().map(String::len).max().unwrap_or(0).max(3);
+        let line_width = self.lines.len().saturating_sub(1).to_string().len().max(4);
+
+        for (name, ranges) in names.iter().zip(self.ranges.values()) {
+            writeln!(f, "{:w$} {}", name, ranges.iter().join(", "), w = width)?;
+        }
+        writeln!(f, "pressure: {}", self.pressure())?;
+        writeln!(f)?;
+
+        write!(f, "{:>w$} ", "line", w = line_width)?;
+        for name in names.iter() {
+            write!(f, " {:^w$}", name, w = width)?;
+        }
+        writeln!(f, " |")?;
+        for (i, line) in self.lines.iter().enumerate() {
+            write!(f, "{:>w$} ", i, w = line_width)?;
+            for r in self.ranges.keys() {
+                let c = if self.defs[i].contains(r) {
+                    "*"
+                } else if self.occupied[i].contains(r) {
+                    "|"
+                } else {
+                    ""
+                };
+                write!(f, " {:^w$}", c, w = width)?;
+            }
+            writeln!(f, " | {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Live ranges of `lines` per register name
+    fn ranges(lines: &[&str]) -> Vec<(String, String)> {
+        let lifetimes = Lifetimes::compute(&Program::parse(lines));
+        lifetimes
+            .ranges()
+            .iter()
+            .map(|(r, ranges)| (lifetimes.aliases.name(*r), ranges.iter().join(", ")))
+            .collect()
+    }
+
+    fn expected(ranges: &[(&str, &str)]) -> Vec<(String, String)> {
+        ranges
+            .iter()
+            .map(|(r, ranges)| (r.to_string(), ranges.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn straight_lines() {
+        let lines = ["move r0 1", "add r1 r0 1", "s d0 On r1", "move r2 3"];
+        assert_eq!(
+            ranges(&lines),
+            expected(&[("r0", "0-1"), ("r1", "1-2"), ("r2", "3")])
+        );
+        let lifetimes = Lifetimes::compute(&Program::parse(&lines));
+        assert_eq!(lifetimes.pressure(), 2);
+    }
+
+    #[test]
+    fn loops() {
+        // Counter carried around the loop, value loaded on every iteration
+        let lines = [
+            "move r0 0",
+            "loop:",
+            "l r1 d0 Setting",
+            "add r0 r0 r1",
+            "s d1 On r0",
+            "yield",
+            "j loop",
+        ];
+        assert_eq!(ranges(&lines), expected(&[("r0", "0-6"), ("r1", "2-3")]));
+    }
+
+    #[test]
+    fn branches() {
+        // Live across the branch, and written on both paths
+        let lines = [
+            "l r0 d0 Setting",
+            "move r1 5",
+            "beqz r0 skip",
+            "move r1 7",
+            "skip:",
+            "s d1 On r1",
+        ];
+        assert_eq!(ranges(&lines), expected(&[("r0", "0-2"), ("r1", "1-5")]));
+
+        // Only live on each path
+        let lines = [
+            "l r0 d0 Setting",
+            "beqz r0 other",
+            "move r1 1",
+            "s d1 On r1",
+            "j end",
+            "other:",
+            "move r1 2",
+            "s d1 Open r1",
+            "end:",
+        ];
+        assert_eq!(
+            ranges(&lines),
+            expected(&[("r0", "0-1"), ("r1", "2-3, 6-7")])
+        );
+    }
+}
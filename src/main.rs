@@ -6,20 +6,41 @@
 
 use std::fs::File;
 use std::io::{BufRead, BufReader, Result as IOResult};
+use std::process::exit;
 
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
-//use petgraph::graph::{DiGraph, Graph, NodeIndex};
 
 pub mod alias;
+pub mod analysis;
+pub mod behavior;
+pub mod branch;
+pub mod cfg;
 pub mod device;
+pub mod hash;
 pub mod ic;
 pub mod instruction;
+pub mod lifetime;
+pub mod network;
+pub mod optimize;
+pub mod profile;
+pub mod program;
+pub mod scenario;
+pub mod size;
+pub mod timeline;
+pub mod world;
 
 use crate::{
+    cfg::Cfg,
     ic::ICState,
     instruction::{InstructionSet, StationeersInstructionSet},
+    lifetime::Lifetimes,
+    optimize::{MergeTails, Optimizer, Peephole},
+    profile::HardwareProfile,
+    program::Program,
+    scenario::Scenario,
+    size::SizeReport,
 };
 
 lazy_static! {
@@ -27,13 +48,16 @@ lazy_static! {
 }
 
 /// Parse and run a single instruction line
+///
+/// Comments (`# ...`) are ignored and empty lines are no-ops.
 pub fn try_run_line<I: InstructionSet>(
     ic: &mut ICState,
     line: &str,
     line_number: usize,
     instructions: &I,
 ) -> Result<(), String> {
-    let mut tokens = line.split(" ");
+    let line = line.split('#').next().unwrap_or("").trim();
+    let mut tokens = program::tokens(line).into_iter();
     if let Some(label) = PATTERN_LABEL.captures(line).and_then(|m| m.get(1)) {
         ic.add_label(label.as_str(), line_number);
         Ok(())
@@ -41,15 +65,55 @@ pub fn try_run_line<I: InstructionSet>(
         let args = tokens.collect();
         instructions.try_run(instr, args, ic)
     } else {
-        // empty line error?
-        Err(format!("Empty line?"))
+        Ok(())
     }
 }
 
+/// Run a single game tick of a program, starting from where the previous tick stopped.
+///
+/// Nothing is run while the IC's housing is off or after a runtime error, which also
+/// raises the housing's `Error`.
+pub fn try_run_tick<I: InstructionSet>(
+    ic: &mut ICState,
+    lines: &[String],
+    instructions: &I,
+) -> Result<(), String> {
+    if !ic.is_on() || ic.has_error() {
+        return Ok(());
+    }
+    ic.begin_tick();
+    try_run(ic, lines, instructions).inspect_err(|_| ic.set_error(true))
+}
+
+/// Load a program onto an IC: check it fits the IC's hardware profile and register its labels
+pub fn load_program(ic: &mut ICState, lines: &[String]) -> Result<(), String> {
+    ic.profile().check_program(lines)?;
+    scan_labels(ic, lines);
+    Ok(())
+}
+
+/// Register every label of a program ahead of running it, so forward jumps resolve
+pub fn scan_labels(ic: &mut ICState, lines: &[String]) {
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(label) = PATTERN_LABEL.captures(line.trim()).and_then(|m| m.get(1)) {
+            ic.add_label(label.as_str(), i);
+        }
+    }
+}
+
+/// Read the lines of a script file
+pub fn read_lines(path: &str) -> Result<Vec<String>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open '{}': {}", path, e))?;
+    BufReader::new(file)
+        .lines()
+        .try_collect()
+        .map_err(|e| format!("Failed to read '{}': {}", path, e))
+}
+
 /// Parse and run lines of instructions
 pub fn try_run<I: InstructionSet>(
     ic: &mut ICState,
-    lines: &Vec<String>,
+    lines: &[String],
     instruction: &I,
 ) -> Result<(), String> {
     // Run while:
@@ -60,34 +124,130 @@ pub fn try_run<I: InstructionSet>(
         let i = ic.next_line;
         ic.next_line += 1;
         if let Some(line) = lines.get(i) {
+            ic.set_line_number(i);
             try_run_line(ic, line, i, instruction)?;
             ic.instr_counter += 1;
         } else {
             return Err(format!("Line index '{}' out of range", ic.next_line));
         }
     }
+    ic.set_line_number(ic.next_line);
     Ok(())
 }
 
-fn main() -> IOResult<()> {
-    //for f in [("ap", "na", "eq", "ne", "ge", "gt", "le", "lt"
-    //for (p, s) in [("b", ""), ("b", "z"), ("br", "")].iter() {
-    //}
+const USAGE: &str = "usage:
+    ic-optimizer-rs run <scenario.toml>...
+    ic-optimizer-rs check [--profile <name>] <script>...
+    ic-optimizer-rs cfg <script>
+    ic-optimizer-rs lifetimes <script>
+    ic-optimizer-rs optimize [--profile <name>] [--rules <file>] [--nonvolatile <types>] [--size] <script>";
+
+/// Split a leading `--profile <name>` option (defaulting to `ic10`) from the arguments
+fn profile_option(args: &[String]) -> Result<(HardwareProfile, &[String]), String> {
+    match args {
+        [opt, name, rest @ ..] if opt == "--profile" => Ok((HardwareProfile::by_name(name)?, rest)),
+        [opt] if opt == "--profile" => Err(USAGE.to_owned()),
+        _ => Ok((HardwareProfile::ic10(), args)),
+    }
+}
+
+/// Print the size report of every script. Returns whether all of them fit.
+fn check_scripts(args: &[String]) -> Result<bool, String> {
+    let (profile, paths) = profile_option(args)?;
+    if paths.is_empty() {
+        return Err(USAGE.to_owned());
+    }
+    let mut fits = true;
+    for path in paths {
+        let report = SizeReport::new(&profile, &read_lines(path)?);
+        println!("{}:\n{}", path, report);
+        fits &= report.fits();
+    }
+    Ok(fits)
+}
 
-    //let instructions = StationeersInstructionSet::new();
-    //let mut ic = ICState::default();
+/// Print the control-flow graph of a script in Graphviz format
+fn print_cfg(path: &str) -> Result<bool, String> {
+    let program = Program::parse(&read_lines(path)?);
+    print!("{}", Cfg::build(&program).to_dot());
+    Ok(true)
+}
 
-    //let file = File::open("test.mips").unwrap();
-    //let lines: Vec<String> = BufReader::new(file).lines().try_collect().unwrap();
-    //let mips = lines.join("\n");
+/// Print the live ranges of the registers of a script
+fn print_lifetimes(path: &str) -> Result<bool, String> {
+    let program = Program::parse(&read_lines(path)?);
+    print!("{}", Lifetimes::compute(&program));
+    Ok(true)
+}
 
-    //println!("Running(\n\"\n{}\n\")", mips);
-    //println!("{:?}", try_run(&mut ic, &lines, &instructions));
-    //print!("{}", ic);
-    //println!("ic.instr_counter = {}", ic.instr_counter);
+/// Print the optimized script, and its size before and after to stderr.
+/// Returns whether the optimized script fits the profile.
+fn optimize_script(args: &[String]) -> Result<bool, String> {
+    let (profile, mut args) = profile_option(args)?;
+    let mut rules = None;
+    let mut nonvolatile = Vec::new();
+    let mut size = false;
+    let path = loop {
+        match args {
+            [opt, file, rest @ ..] if opt == "--rules" => {
+                rules = Some(Peephole::from_file(file)?);
+                args = rest;
+            }
+            [opt, types, rest @ ..] if opt == "--nonvolatile" => {
+                nonvolatile.extend(types.split(',').map(str::to_owned));
+                args = rest;
+            }
+            [opt, rest @ ..] if opt == "--size" => {
+                size = true;
+                args = rest;
+            }
+            [path] => break path,
+            _ => return Err(USAGE.to_owned()),
+        }
+    };
+    let mut optimizer = Optimizer::with_nonvolatile(profile.clone(), nonvolatile);
+    if let Some(rules) = rules {
+        optimizer = optimizer.with_pass(Box::new(rules));
+    }
+    if size {
+        optimizer = optimizer.with_pass(Box::new(MergeTails));
+    }
+    let lines = read_lines(path)?;
+    let optimized = optimizer.optimize(&Program::parse(&lines))?.to_lines();
+    let before = SizeReport::new(&profile, &lines);
+    let after = SizeReport::new(&profile, &optimized);
+    eprintln!("before:\n{}\nafter:\n{}", before, after);
+    println!("{}", optimized.join("\n"));
+    Ok(after.fits())
+}
 
-    //let mut graph = DiGraph::new();
-    //let a = graph.add_node(0);
+/// Run every scenario, printing its report. Returns whether all of them passed.
+fn run_scenarios(paths: &[String]) -> Result<bool, String> {
+    let mut passed = true;
+    for path in paths {
+        let report = Scenario::from_file(path)?.run()?;
+        println!("{}:\n{}", path, report);
+        passed &= report.passed();
+    }
+    Ok(passed)
+}
 
-    Ok(())
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((cmd, rest)) if cmd == "run" && !rest.is_empty() => run_scenarios(rest),
+        Some((cmd, rest)) if cmd == "check" => check_scripts(rest),
+        Some((cmd, [path])) if cmd == "cfg" => print_cfg(path),
+        Some((cmd, [path])) if cmd == "lifetimes" => print_lifetimes(path),
+        Some((cmd, rest)) if cmd == "optimize" => optimize_script(rest),
+        _ => Err(USAGE.to_owned()),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    }
 }
@@ -1,13 +1,157 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
 
-use crate::device::DeviceType;
+use crate::device::{DeviceRef, DeviceType};
 
-type NetworkDeviceId = usize;
+pub type ReferenceId = usize;
 
-struct Network {
-    devices: HashMap<String, Vec<DeviceType>>,
+/// Shared handle to a network, so that ICs can reach its devices while running
+pub type NetworkRef = Rc<RefCell<Network>>;
+
+/// How batch loads (`lb`, `lbn`, `lbs`, `lbns`) combine the values of several devices
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatchMode {
+    Average,
+    Sum,
+    Minimum,
+    Maximum,
+}
+
+impl BatchMode {
+    /// Parse a batch mode given either by name or by number
+    pub fn parse(token: &str) -> Result<Self, String> {
+        match token {
+            "Average" | "0" => Ok(BatchMode::Average),
+            "Sum" | "1" => Ok(BatchMode::Sum),
+            "Minimum" | "2" => Ok(BatchMode::Minimum),
+            "Maximum" | "3" => Ok(BatchMode::Maximum),
+            _ => Err(format!("'{}' is not a batch mode", token)),
+        }
+    }
+
+    /// Combine `values`, an empty batch yields 0
+    pub fn apply(&self, values: &[f64]) -> f64 {
+        if values.is_empty() {
+            return 0.0;
+        }
+        match self {
+            BatchMode::Average => values.iter().sum::<f64>() / values.len() as f64,
+            BatchMode::Sum => values.iter().sum(),
+            BatchMode::Minimum => values.iter().cloned().fold(f64::INFINITY, f64::min),
+            BatchMode::Maximum => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+/// Data network that devices (and IC housings) are connected to.
+///
+/// Devices are owned by networks, ICs reference them through their pins or, for the
+/// network their housing is connected to, by reference id and hashes.
+pub struct Network {
+    name: String,
+    devices: BTreeMap<ReferenceId, DeviceRef>,
 }
 
 impl Network {
-    //pub fn add_device(&mut self,
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            devices: BTreeMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Connect a device under reference id `id`, exposing it as its `ReferenceId` parameter
+    pub fn add_device(&mut self, id: ReferenceId, dt: DeviceType) -> DeviceRef {
+        let d: DeviceRef = dt.into();
+        self.connect(id, d.clone());
+        d
+    }
+
+    /// Connect an existing (possibly shared) device under reference id `id`
+    pub fn connect(&mut self, id: ReferenceId, d: DeviceRef) {
+        d.borrow_mut().set_param("ReferenceId", id as f64);
+        self.devices.insert(id, d);
+    }
+
+    pub fn get_device(&self, id: ReferenceId) -> Option<&DeviceRef> {
+        self.devices.get(&id)
+    }
+
+    /// First device with the label `label`
+    pub fn find_device(&self, label: &str) -> Option<&DeviceRef> {
+        self.devices.values().find(|d| d.borrow().label() == label)
+    }
+
+    /// Devices with prefab hash `prefab_hash` and, if given, name hash `name_hash`
+    pub fn find_devices(&self, prefab_hash: i32, name_hash: Option<i32>) -> Vec<DeviceRef> {
+        self.devices
+            .values()
+            .filter(|d| {
+                let d = d.borrow();
+                d.prefab_hash() == prefab_hash && name_hash.is_none_or(|h| d.name_hash() == h)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Devices in reference id order
+    pub fn devices(&self) -> impl Iterator<Item = (ReferenceId, &DeviceRef)> {
+        self.devices.iter().map(|(id, d)| (*id, d))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash;
+
+    #[test]
+    fn batch_modes() {
+        let values = [1.0, 4.0, -2.0, 5.0];
+        let modes = [
+            ("Average", "0", 2.0),
+            ("Sum", "1", 8.0),
+            ("Minimum", "2", -2.0),
+            ("Maximum", "3", 5.0),
+        ];
+        for (name, number, combined) in modes {
+            let mode = BatchMode::parse(name).unwrap();
+            assert_eq!(BatchMode::parse(number), Ok(mode));
+            assert_eq!(mode.apply(&values), combined, "{}", name);
+            assert_eq!(mode.apply(&[]), 0.0, "{}", name);
+        }
+        assert!(BatchMode::parse("4").is_err());
+        assert!(BatchMode::parse("Total").is_err());
+    }
+
+    #[test]
+    fn devices_by_hashes() {
+        let mut network = Network::new("base");
+        for (id, prefab, label) in [
+            (1, "StructureWallLight", "Hallway"),
+            (2, "StructureWallLight", "Kitchen"),
+            (3, "StructureGasSensor", "Hallway"),
+        ] {
+            let mut dt = DeviceType::new(prefab);
+            dt.set_label(label);
+            network.add_device(id, dt);
+        }
+        let ids = |prefab: i32, name: Option<i32>| -> Vec<f64> {
+            let devices = network.find_devices(prefab, name);
+            devices
+                .iter()
+                .map(|d| d.borrow().get_param("ReferenceId"))
+                .collect()
+        };
+        let light = hash("StructureWallLight");
+        assert_eq!(ids(light, None), [1.0, 2.0]);
+        assert_eq!(ids(light, Some(hash("Kitchen"))), [2.0]);
+        assert_eq!(ids(light, Some(hash("Garage"))), [] as [f64; 0]);
+        assert_eq!(ids(hash("StructureLogicMemory"), None), [] as [f64; 0]);
+        // Hashes this large are not all f32 values, neighbours must not match
+        assert_eq!(ids(light + 1, None), [] as [f64; 0]);
+    }
 }
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    cfg::{resolve_target, Cfg, Target},
+    profile::HardwareProfile,
+    program::{fresh_name, Line, Program},
+    size::SizeReport,
+};
+
+mod algebra;
+mod bindings;
+mod compare_branch;
+mod constants;
+mod copies;
+mod cse;
+mod dead_code;
+mod inline;
+mod jumps;
+mod labels;
+mod layout;
+mod licm;
+mod peephole;
+mod registers;
+mod select;
+mod tails;
+#[cfg(test)]
+mod tests;
+
+pub use algebra::Simplify;
+pub use bindings::EliminateBindings;
+pub use compare_branch::FuseCompareBranch;
+pub use constants::Constants;
+pub use copies::CopyPropagation;
+pub use cse::CommonSubexpressions;
+pub use dead_code::DeadCode;
+pub use inline::InlineSubroutines;
+pub use jumps::SimplifyJumps;
+pub use labels::ResolveLabels;
+pub use layout::BlockLayout;
+pub use licm::HoistInvariants;
+pub use peephole::Peephole;
+pub use registers::RegisterAllocation;
+pub use select::BranchToSelect;
+pub use tails::MergeTails;
+
+/// Program transformation, preserving what the program does
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    /// Rewrite `program`, which has only label or `ra` jump targets. Returns whether
+    /// anything changed.
+    fn run(&self, program: &mut Program, profile: &HardwareProfile) -> Result<bool, String>;
+}
+
+/// Bound on rounds of passes, in case passes keep undoing each other
+const MAX_ROUNDS: usize = 32;
+
+/// Runs passes over a program until none of them changes it, then the final passes once
+pub struct Optimizer {
+    profile: HardwareProfile,
+    passes: Vec<Box<dyn Pass>>,
+    final_passes: Vec<Box<dyn Pass>>,
+}
+
+impl Optimizer {
+    /// Optimizer running every pass, for programs running on `profile`
+    pub fn new(profile: HardwareProfile) -> Self {
+        Self::with_nonvolatile(profile, Vec::new())
+    }
+
+    /// Optimizer running every pass, treating loads of the logic types `nonvolatile` as
+    /// reading the same value until the program stores to a device
+    pub fn with_nonvolatile(profile: HardwareProfile, nonvolatile: Vec<String>) -> Self {
+        Self::with_passes(
+            profile,
+            vec![
+                Box::new(InlineSubroutines),
+                Box::new(Constants),
+                Box::new(Simplify),
+                Box::new(CommonSubexpressions::new(nonvolatile)),
+                Box::new(CopyPropagation),
+                Box::new(HoistInvariants),
+                Box::new(DeadCode),
+                Box::new(FuseCompareBranch),
+                Box::new(BranchToSelect),
+                Box::new(SimplifyJumps),
+                Box::new(BlockLayout),
+                Box::new(EliminateBindings),
+                Box::new(RegisterAllocation),
+            ],
+        )
+        .then(Box::new(ResolveLabels))
+    }
+
+    pub fn with_passes(profile: HardwareProfile, passes: Vec<Box<dyn Pass>>) -> Self {
+        Self {
+            profile,
+            passes,
+            final_passes: Vec::new(),
+        }
+    }
+
+    /// Also run `pass` with the other passes
+    pub fn with_pass(mut self, pass: Box<dyn Pass>) -> Self {
+        self.passes.push(pass);
+        self
+    }
+
+    /// Also run `pass` once the other passes are done, for passes leaving programs other
+    /// passes cannot work on
+    pub fn then(mut self, pass: Box<dyn Pass>) -> Self {
+        self.final_passes.push(pass);
+        self
+    }
+
+    pub fn optimize(&self, program: &Program) -> Result<Program, String> {
+        let mut program = normalize(program)?;
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for pass in self.passes.iter() {
+                changed |= pass
+                    .run(&mut program, &self.profile)
+                    .map_err(|e| format!("{}: {}", pass.name(), e))?;
+            }
+            if !changed {
+                break;
+            }
+        }
+        for pass in self.final_passes.iter() {
+            pass.run(&mut program, &self.profile)
+                .map_err(|e| format!("{}: {}", pass.name(), e))?;
+        }
+        Ok(program)
+    }
+}
+
+/// Replace line number jump targets, absolute or relative, with labels, so that passes
+/// can add and remove lines.
+///
+/// Fails for programs jumping to targets computed at runtime (other than `ra`).
+pub fn normalize(program: &Program) -> Result<Program, String> {
+    let cfg = Cfg::build(program);
+    if let Some(line) = cfg.indirect_lines().first() {
+        return Err(format!(
+            "Line {} jumps to a target computed at runtime, lines cannot be moved",
+            line
+        ));
+    }
+    let labels = program.labels();
+    let mut result = program.clone();
+    // Labels to add, by target line
+    let mut added: BTreeMap<usize, String> = BTreeMap::new();
+    for (i, instr) in program.instructions() {
+        let target = match resolve_target(program, i, instr) {
+            Some(Target::Line(l)) => l,
+            _ => continue,
+        };
+        let token = instr.target().unwrap();
+        if labels.get(token) == Some(&target) {
+            continue;
+        }
+        let label = match program.lines.get(target) {
+            Some(Line::Label(l)) => l.clone(),
+            _ => {
+                let taken =
+                    |name: &str| program.is_name_taken(name) || added.values().any(|l| l == name);
+                let label = fresh_name(&format!("line{}", target), taken);
+                added.entry(target).or_insert(label).clone()
+            }
+        };
+        let mut branch = instr.branch().unwrap();
+        branch.relative = false;
+        let instr = result.lines[i].instruction_mut().unwrap();
+        instr.op = branch.op();
+        *instr.args.last_mut().unwrap() = label;
+    }
+    for (line, label) in added.into_iter().rev() {
+        result
+            .lines
+            .insert(line.min(result.len()), Line::Label(label));
+    }
+    Ok(result)
+}
+
+/// Size of a program's lines against the profile
+pub fn size(program: &Program, profile: &HardwareProfile) -> SizeReport {
+    SizeReport::new(profile, &program.to_lines())
+}
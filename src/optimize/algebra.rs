@@ -0,0 +1,209 @@
+use crate::{
+    ic::ICState,
+    instruction::{InstructionSet, StationeersInstructionSet},
+    profile::HardwareProfile,
+    program::{literal_value, Instruction, Line, Program},
+};
+
+use super::Pass;
+
+/// Applies algebraic identities to math instructions:
+///
+/// * `mul r x 1`, `div r x 1`, `sub r x 0`, `max r x x`, `min r x x` and
+///   `select r c x x` become `move r x`, and `move r r` is removed,
+/// * `mul r x 2` becomes `add r x x`, `div r x 4` becomes `mul r x 0.25`,
+/// * clamps `max r x a`, `max r r b` become `max r x b` (for `b > a`), and
+///   `min r x a`, `max r r b` become `move r b` (for `b >= a`), likewise swapping `min`
+///   and `max`.
+///
+/// Every rewrite is checked by running both versions on the simulator for operands
+/// including zeroes, infinities and NaN, and only made when they agree bit for bit.
+/// `sub r x x` is thus not `move r 0`, as it is NaN for infinite or NaN `x`, and
+/// `add r x 0` is not `move r x`, as it is `0` for `x = -0`.
+pub struct Simplify;
+
+/// Operand values rewrites are checked with
+const SAMPLES: &[f64] = &[
+    0.0,
+    -0.0,
+    1.0,
+    -1.0,
+    0.5,
+    2.0,
+    3.0,
+    -7.25,
+    1e30,
+    -1e30,
+    f64::MIN_POSITIVE,
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+    f64::NAN,
+];
+
+/// Whether `rewritten` leaves register `dest` with the same value as `original`, for every
+/// combination of sample values of their non-literal operands
+fn holds(original: &[Instruction], rewritten: &[Instruction], dest: &str) -> bool {
+    let mut operands: Vec<&str> = vec![dest];
+    for instr in original.iter().chain(rewritten.iter()) {
+        for a in instr.args.iter().filter(|a| literal_value(a).is_none()) {
+            if !operands.contains(&a.as_str()) {
+                operands.push(a);
+            }
+        }
+    }
+    let registers: Vec<String> = (0..operands.len()).map(|i| format!("r{}", i)).collect();
+    let rename = |instrs: &[Instruction]| -> Vec<Instruction> {
+        instrs
+            .iter()
+            .map(|i| {
+                let args: Vec<&str> = i
+                    .args
+                    .iter()
+                    .map(|a| match operands.iter().position(|o| o == a) {
+                        Some(k) => registers[k].as_str(),
+                        None => a.as_str(),
+                    })
+                    .collect();
+                Instruction::new(&i.op, &args)
+            })
+            .collect()
+    };
+    let (original, rewritten) = (rename(original), rename(rewritten));
+    let instructions = StationeersInstructionSet::new();
+    let run = |instrs: &[Instruction], values: &[f64]| -> Option<f64> {
+        let mut ic = ICState::default();
+        for (r, v) in registers.iter().zip(values) {
+            let r = ic.try_register(r).ok()?;
+            ic.set_register(r, *v).ok()?;
+        }
+        for i in instrs {
+            let args = i.args.iter().map(String::as_str).collect();
+            instructions.try_run(&i.op, args, &mut ic).ok()?;
+        }
+        ic.get_register(ic.try_register("r0").ok()?).ok()
+    };
+
+    let mut values = vec![0.0; operands.len()];
+    let mut combination = vec![0; operands.len()];
+    loop {
+        for (v, k) in values.iter_mut().zip(combination.iter()) {
+            *v = SAMPLES[*k];
+        }
+        let same = match (run(&original, &values), run(&rewritten, &values)) {
+            (Some(a), Some(b)) => a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan()),
+            _ => false,
+        };
+        if !same {
+            return false;
+        }
+        // Next combination, counting in base `SAMPLES.len()`
+        let mut k = 0;
+        loop {
+            if k == combination.len() {
+                return true;
+            }
+            combination[k] += 1;
+            if combination[k] < SAMPLES.len() {
+                break;
+            }
+            combination[k] = 0;
+            k += 1;
+        }
+    }
+}
+
+/// Candidate rewrite of a single instruction
+fn simplify(instr: &Instruction) -> Option<Vec<Instruction>> {
+    let is = |token: &str, value: f64| literal_value(token) == Some(value);
+    let mov = |r: &str, x: &str| vec![Instruction::new("move", &[r, x])];
+    let rewritten = match (instr.op.as_str(), instr.args.as_slice()) {
+        ("move", [r, x]) if r == x => vec![],
+        ("mul", [r, x, one]) | ("mul", [r, one, x]) if is(one, 1.0) => mov(r, x),
+        ("div", [r, x, one]) if is(one, 1.0) => mov(r, x),
+        ("sub", [r, x, zero]) if is(zero, 0.0) => mov(r, x),
+        ("sub", [r, x, y]) if x == y => mov(r, "0"),
+        ("max", [r, x, y]) | ("min", [r, x, y]) if x == y => mov(r, x),
+        ("select", [r, _, x, y]) if x == y => mov(r, x),
+        ("mul", [r, x, two]) | ("mul", [r, two, x])
+            if is(two, 2.0) && literal_value(x).is_none() =>
+        {
+            vec![Instruction::new("add", &[r, x, x])]
+        }
+        ("div", [r, x, c]) => {
+            // Only powers of two have a reciprocal without rounding
+            let c = literal_value(c)
+                .filter(|c| c.is_normal() && c.to_bits() & 0xf_ffff_ffff_ffff == 0)?;
+            vec![Instruction::new("mul", &[r, x, &c.recip().to_string()])]
+        }
+        _ => return None,
+    };
+    Some(rewritten)
+}
+
+/// Candidate rewrite of a clamp, two `max`/`min` with constant bounds writing the same
+/// register
+fn simplify_clamp(first: &Instruction, second: &Instruction) -> Option<Vec<Instruction>> {
+    let (op, r, x, a) = match (first.op.as_str(), first.args.as_slice()) {
+        (op @ ("max" | "min"), [r, x, a]) => (op, r, x, literal_value(a)?),
+        _ => return None,
+    };
+    let b = match (second.op.as_str(), second.args.as_slice()) {
+        (op2, [r2, r3, b]) if matches!(op2, "max" | "min") && r2 == r && r3 == r => {
+            (op2, literal_value(b)?)
+        }
+        _ => return None,
+    };
+    let rewritten = match b {
+        // Both bounds on the same side, the tighter one wins
+        (op2, b) if op2 == op => {
+            let bound = if op == "max" { a.max(b) } else { a.min(b) };
+            Instruction::new(op, &[r, x, &bound.to_string()])
+        }
+        // The second bound is past the first, it is the result
+        (_, b) => Instruction::new("move", &[r, &b.to_string()]),
+    };
+    Some(vec![rewritten])
+}
+
+impl Pass for Simplify {
+    fn name(&self) -> &'static str {
+        "algebra"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let mut edits: Vec<(usize, usize, Vec<Instruction>)> = Vec::new();
+        let mut i = 0;
+        while i < program.len() {
+            let instr = match program.lines[i].instruction() {
+                Some(instr) => instr,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let dest = instr.args.first().map(String::as_str).unwrap_or("");
+            let next = program.lines.get(i + 1).and_then(Line::instruction);
+            let clamp = next.and_then(|n| {
+                simplify_clamp(instr, n).filter(|s| holds(&[instr.clone(), n.clone()], s, dest))
+            });
+            if let Some(rewritten) = clamp {
+                edits.push((i, 2, rewritten));
+                i += 2;
+                continue;
+            }
+            if let Some(rewritten) =
+                simplify(instr).filter(|s| holds(std::slice::from_ref(instr), s, dest))
+            {
+                edits.push((i, 1, rewritten));
+            }
+            i += 1;
+        }
+        let changed = !edits.is_empty();
+        // Edit from the last line up, so earlier line indices stay valid
+        for (i, n, rewritten) in edits.into_iter().rev() {
+            let lines = rewritten.into_iter().map(Line::Instruction);
+            program.lines.splice(i..i + n, lines);
+        }
+        Ok(changed)
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    analysis::forward,
+    cfg::Cfg,
+    instruction::{signature, ArgKind},
+    profile::HardwareProfile,
+    program::{Instruction, Program},
+};
+
+use super::{size, Pass};
+
+/// Replaces names bound by `alias` and `define` with the register, pin or value they stand
+/// for, then removes the declarations, saving a line (and an executed instruction) each.
+///
+/// Bindings are tracked per program point, so a name aliased to different registers in
+/// different parts of the program is resolved wherever it is used. Names bound to
+/// different things on paths meeting at a use keep their declarations, as do names whose
+/// replacement would make a line too long.
+pub struct EliminateBindings;
+
+/// What a name stands for at a program point
+#[derive(Clone, Debug, PartialEq)]
+enum Binding {
+    Token(String),
+    /// Bound differently on different paths
+    Conflict,
+}
+
+type Bindings = HashMap<String, Binding>;
+
+fn join(a: &Bindings, b: &Bindings) -> Bindings {
+    let mut joined = a.clone();
+    for (name, binding) in b.iter() {
+        match joined.get(name) {
+            Some(other) if other != binding => {
+                joined.insert(name.clone(), Binding::Conflict);
+            }
+            Some(_) => {}
+            // Using a name on a path it is not bound on fails, leave the other path's
+            None => {
+                joined.insert(name.clone(), binding.clone());
+            }
+        }
+    }
+    joined
+}
+
+fn transfer(instr: &Instruction, bindings: &mut Bindings) {
+    let binding = match (instr.op.as_str(), instr.args.as_slice()) {
+        ("alias", [name, target]) => {
+            let target = match bindings.get(target) {
+                Some(b) => b.clone(),
+                None => Binding::Token(target.clone()),
+            };
+            (name, target)
+        }
+        ("define", [name, value]) => (name, Binding::Token(value.clone())),
+        _ => return,
+    };
+    bindings.insert(binding.0.clone(), binding.1);
+}
+
+/// Whether argument `i` of `instr` is the name declared by an `alias` or `define`
+fn is_declared_name(instr: &Instruction, i: usize) -> bool {
+    matches!(instr.op.as_str(), "alias" | "define") && i == 0
+}
+
+/// Indices of the arguments of `instr` that may be names bound by `alias` or `define`
+fn bindable_args(instr: &Instruction) -> Vec<usize> {
+    let kinds = match signature(&instr.op) {
+        Some(kinds) if kinds.len() == instr.args.len() => kinds,
+        _ => return Vec::new(),
+    };
+    kinds
+        .iter()
+        .enumerate()
+        .filter(|(i, k)| **k != ArgKind::Token && !is_declared_name(instr, *i))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+impl Pass for EliminateBindings {
+    fn name(&self) -> &'static str {
+        "bindings"
+    }
+
+    fn run(&self, program: &mut Program, profile: &HardwareProfile) -> Result<bool, String> {
+        let declared: BTreeSet<String> = program
+            .instructions()
+            .filter(|(_, i)| is_declared_name(i, 0) && !i.args.is_empty())
+            .map(|(_, i)| i.args[0].clone())
+            .collect();
+        if declared.is_empty() {
+            return Ok(false);
+        }
+        let labels = program.labels();
+        let cfg = Cfg::build(program);
+        let bindings_in = forward(program, &cfg, Bindings::new(), join, transfer);
+
+        // Names with a use that cannot be resolved keep their declarations
+        let mut kept: BTreeSet<&String> = BTreeSet::new();
+        let mut result = program.clone();
+        for (i, instr) in program.instructions() {
+            for arg in bindable_args(instr) {
+                let name = &instr.args[arg];
+                if !declared.contains(name) || labels.contains_key(name) {
+                    continue;
+                }
+                match bindings_in[i].as_ref().and_then(|b| b.get(name)) {
+                    Some(Binding::Token(token)) => {
+                        result.lines[i].instruction_mut().unwrap().args[arg] = token.clone();
+                    }
+                    _ => {
+                        kept.insert(name);
+                    }
+                }
+            }
+        }
+
+        // Keep the names of lines their replacements make too long
+        let was_long: BTreeSet<usize> = size(program, profile)
+            .long_lines
+            .iter()
+            .map(|(i, _)| *i)
+            .collect();
+        while let Some(i) = size(&result, profile)
+            .long_lines
+            .iter()
+            .map(|(i, _)| *i)
+            .find(|i| !was_long.contains(i))
+        {
+            let orig = program.lines[i].instruction().unwrap();
+            let names: Vec<&String> = orig.args.iter().filter(|a| declared.contains(*a)).collect();
+            for (line, orig) in result.lines.iter_mut().zip(program.lines.iter()) {
+                if let (Some(instr), Some(orig)) = (line.instruction_mut(), orig.instruction()) {
+                    for (arg, orig_arg) in instr.args.iter_mut().zip(orig.args.iter()) {
+                        if names.contains(&orig_arg) {
+                            *arg = orig_arg.clone();
+                        }
+                    }
+                }
+            }
+            kept.extend(names);
+        }
+
+        result.lines.retain(|l| match l.instruction() {
+            Some(instr) if is_declared_name(instr, 0) && !instr.args.is_empty() => {
+                kept.contains(&instr.args[0])
+            }
+            _ => true,
+        });
+        let changed = result != *program;
+        *program = result;
+        Ok(changed)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{
+    analysis::{Aliases, Liveness},
+    branch::Condition,
+    cfg::Cfg,
+    instruction::signature,
+    profile::HardwareProfile,
+    program::{Instruction, Line, Program},
+};
+
+use super::Pass;
+
+/// Fuses a set instruction into a register followed by a `beqz` or `bnez` on it into the
+/// single branch testing the same condition, e.g. `slt t a b` then `bnez t L` into
+/// `blt a b L`, or `seq t a b` then `beqz t L` into `bne a b L`. Only done when the
+/// register is not read afterwards, saving a line and a register.
+///
+/// `beqz` after an ordered comparison is left alone: `slt` stores 0 when an operand is
+/// NaN, and `bge` does not branch then.
+pub struct FuseCompareBranch;
+
+impl FuseCompareBranch {
+    /// Branch testing what `set` stored, in place of `branch`
+    fn fuse(set: &Instruction, branch: &Instruction) -> Option<Instruction> {
+        let mut fused = branch.branch().filter(|b| !b.relative)?;
+        let tested = match fused.condition?.name().as_str() {
+            "nez" => Condition::from_set_op(&set.op)?,
+            "eqz" => Condition::from_set_op(&set.op)?.inverse()?,
+            _ => return None,
+        };
+        if branch.args.len() != 2 || set.args.len() != tested.operands() + 1 {
+            return None;
+        }
+        fused.condition = Some(tested);
+        let op = fused.op();
+        signature(&op)?;
+        let args: Vec<&str> = set.args[1..]
+            .iter()
+            .chain(branch.args[1..].iter())
+            .map(String::as_str)
+            .collect();
+        Some(Instruction::new(&op, &args))
+    }
+}
+
+impl Pass for FuseCompareBranch {
+    fn name(&self) -> &'static str {
+        "compare-branch"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let aliases = Aliases::of(program);
+        let liveness = Liveness::compute(program, &Cfg::build(program));
+        let mut fused = Vec::new();
+        for i in 1..program.len() {
+            if let (Line::Instruction(set), Line::Instruction(branch)) =
+                (&program.lines[i - 1], &program.lines[i])
+            {
+                // The branch tests the register the set instruction writes, read nowhere else
+                let temp = set.args.first().filter(|t| branch.args.first() == Some(t));
+                let dead = temp
+                    .and_then(|t| aliases.register(t))
+                    .is_some_and(|t| !liveness.is_live_after(i, t));
+                let overlaps = fused.last().is_some_and(|(l, _)| *l == i - 1);
+                if dead && !overlaps {
+                    fused.extend(Self::fuse(set, branch).map(|f| (i, f)));
+                }
+            }
+        }
+        let changed = !fused.is_empty();
+        for (i, branch) in fused.into_iter().rev() {
+            program.lines[i] = Line::Instruction(branch);
+            program.lines.remove(i - 1);
+        }
+        Ok(changed)
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use crate::{
+    analysis::{forward, Aliases, Effects, Liveness, Register},
+    branch::Comparison,
+    cfg::Cfg,
+    ic::ICState,
+    instruction::{signature, ArgKind, InstructionSet, StationeersInstructionSet},
+    profile::HardwareProfile,
+    program::{literal_value, Instruction, Line, Program},
+};
+
+use super::Pass;
+
+/// Instructions that cannot be folded, their result is not a function of their operands
+const NOT_FOLDABLE: &[&str] = &["rand"];
+
+/// Propagates constants from `define`s, literals and `move`s through registers, folds
+/// instructions with constant operands into `move`s and resolves branches with constant
+/// conditions (into jumps, or nothing). The dead code left behind is removed by
+/// [`DeadCode`](super::DeadCode).
+///
+/// Linking branches not taken are kept when `ra` is read afterwards, as they set it
+/// anyway. Registers are only replaced by their value when the line gets no longer,
+/// `0.33333334` takes more room than `r0`.
+pub struct Constants;
+
+/// Registers known to hold a constant
+type Known = HashMap<Register, f64>;
+
+/// Run `op` on a scratch IC, with `args` after the result register `r0`
+fn evaluate(instructions: &StationeersInstructionSet, op: &str, args: &[f64]) -> Option<f64> {
+    let mut ic = ICState::default();
+    let args: Vec<String> = args.iter().map(f64::to_string).collect();
+    let args = std::iter::once("r0")
+        .chain(args.iter().map(String::as_str))
+        .collect();
+    instructions.try_run(op, args, &mut ic).ok()?;
+    let r0 = ic.try_register("r0").ok()?;
+    ic.get_register(r0).ok().filter(|v| v.is_finite())
+}
+
+struct Folder<'a> {
+    program: &'a Program,
+    aliases: Aliases,
+    defines: HashMap<String, f64>,
+    liveness: Liveness,
+    instructions: StationeersInstructionSet,
+}
+
+impl<'a> Folder<'a> {
+    fn new(program: &'a Program) -> Self {
+        Self {
+            program,
+            aliases: Aliases::of(program),
+            defines: program.defines(),
+            liveness: Liveness::compute(program, &Cfg::build(program)),
+            instructions: StationeersInstructionSet::new(),
+        }
+    }
+
+    /// Value of operand `token` if it is constant
+    fn value(&self, token: &str, known: &Known) -> Option<f64> {
+        if let Some(r) = self.aliases.register(token) {
+            known.get(&r).cloned()
+        } else if let Some(v) = self.defines.get(token) {
+            Some(*v)
+        } else {
+            literal_value(token)
+        }
+    }
+
+    /// Values of the number operands of `instr`, if they are all constant
+    fn operands(&self, instr: &Instruction, known: &Known) -> Option<Vec<f64>> {
+        let kinds = signature(&instr.op).filter(|k| k.len() == instr.args.len())?;
+        instr
+            .args
+            .iter()
+            .zip(kinds)
+            .filter(|(_, k)| **k == ArgKind::Number)
+            .map(|(a, _)| self.value(a, known))
+            .collect()
+    }
+
+    /// Constant written by `instr`, if it only computes a register from its operands
+    fn result(&self, instr: &Instruction, known: &Known) -> Option<(Register, f64)> {
+        if NOT_FOLDABLE.contains(&instr.op.as_str()) {
+            return None;
+        }
+        let kinds = signature(&instr.op)?;
+        match kinds.split_first() {
+            Some((ArgKind::Register, rest)) if rest.iter().all(|k| *k == ArgKind::Number) => {}
+            _ => return None,
+        }
+        let r = self.aliases.register(instr.args.first()?)?;
+        let args = self.operands(instr, known).filter(|a| !a.is_empty())?;
+        Some((r, evaluate(&self.instructions, &instr.op, &args)?))
+    }
+
+    /// Update `known` past instruction `instr`
+    fn transfer(&self, instr: &Instruction, known: &mut Known) {
+        let result = self.result(instr, known);
+        let effects = Effects::of(instr, &self.aliases);
+        if effects.defs_unknown {
+            known.clear();
+        }
+        for r in effects.may_defs.iter() {
+            known.remove(r);
+        }
+        if let Some((r, v)) = result {
+            known.insert(r, v);
+        }
+    }
+
+    /// Registers known to be constant before every line
+    fn analyze(&self) -> Vec<Option<Known>> {
+        let join = |a: &Known, b: &Known| -> Known {
+            a.iter()
+                .filter(|(r, v)| b.get(r).is_some_and(|w| w.to_bits() == v.to_bits()))
+                .map(|(r, v)| (*r, *v))
+                .collect()
+        };
+        let cfg = Cfg::build(self.program);
+        forward(self.program, &cfg, Known::new(), join, |i, k| {
+            self.transfer(i, k)
+        })
+    }
+
+    /// Line `line` rewritten with the constants `known` before it
+    fn rewrite(&self, line: usize, instr: &Instruction, known: &Known) -> Line {
+        let kinds = match signature(&instr.op).filter(|k| k.len() == instr.args.len()) {
+            Some(kinds) => kinds,
+            None => return Line::Instruction(instr.clone()),
+        };
+        if let Some(branch) = instr.branch() {
+            let taken = self.condition(instr, known);
+            // Linking branches write `ra` even when not taken
+            let keep = branch.link && self.liveness.is_live_after(line, Register::Ra);
+            match taken {
+                Some(false) if keep => {}
+                Some(false) => return Line::Empty,
+                Some(true) => {
+                    let mut jump = branch;
+                    jump.condition = None;
+                    return Line::Instruction(Instruction {
+                        op: jump.op(),
+                        args: vec![instr.args.last().unwrap().clone()],
+                    });
+                }
+                None => {}
+            }
+        }
+        // Fold to a `move` of the result, unless that makes the line longer
+        if let Some((_, v)) = self.result(instr, known) {
+            let folded = Instruction::new("move", &[&instr.args[0], &v.to_string()]);
+            if folded.to_string().len() <= instr.to_string().len() {
+                return Line::Instruction(folded);
+            }
+        }
+        if let (Some(c), Some(r)) = (self.select(instr, known), instr.args.first()) {
+            return Line::Instruction(Instruction::new("move", &[r, c]));
+        }
+        // Substitute constant registers, unless that makes the line longer
+        let mut instr = instr.clone();
+        for (a, k) in instr.args.iter_mut().zip(kinds) {
+            if *k == ArgKind::Number && self.aliases.register(a).is_some() {
+                match self.value(a, known).map(|v| v.to_string()) {
+                    Some(v) if v.len() <= a.len() => *a = v,
+                    _ => {}
+                }
+            }
+        }
+        Line::Instruction(instr)
+    }
+
+    /// Whether a branch is taken, if its operands are constant
+    fn condition(&self, instr: &Instruction, known: &Known) -> Option<bool> {
+        let condition = instr.branch()?.condition?;
+        if matches!(condition.comparison, Comparison::Dse | Comparison::Dns) {
+            return None;
+        }
+        let args = self.operands(instr, known)?;
+        evaluate(&self.instructions, &condition.set_op(), &args).map(|v| v != 0.0)
+    }
+
+    /// Operand picked by a `select` with a constant condition
+    fn select<'b>(&self, instr: &'b Instruction, known: &Known) -> Option<&'b str> {
+        match (instr.op.as_str(), instr.args.as_slice()) {
+            ("select", [_, a, b, c]) => {
+                let a = self.value(a, known)?;
+                let picked = evaluate(&self.instructions, "select", &[a, 1.0, 0.0])?;
+                Some(if picked != 0.0 { b } else { c })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Pass for Constants {
+    fn name(&self) -> &'static str {
+        "constants"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let folder = Folder::new(program);
+        let known_in = folder.analyze();
+        let lines: Vec<Line> = program
+            .lines
+            .iter()
+            .zip(known_in.iter())
+            .enumerate()
+            .map(|(i, (line, known))| match (line, known) {
+                (Line::Instruction(instr), Some(known)) => folder.rewrite(i, instr, known),
+                _ => line.clone(),
+            })
+            .collect();
+        let changed = lines != program.lines;
+        program.lines = lines;
+        Ok(changed)
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+
+use crate::{
+    analysis::{forward, Aliases, Effects, Liveness, Register},
+    cfg::Cfg,
+    instruction::{signature, ArgKind},
+    profile::HardwareProfile,
+    program::{Instruction, Line, Program},
+};
+
+use super::Pass;
+
+/// Propagates and coalesces copies made by `move`:
+///
+/// * after `move r2 r1`, reads of `r2` read `r1` instead, as long as neither is written,
+///   leaving the `move` to [`DeadCode`](super::DeadCode) when nothing reads `r2` anymore,
+/// * `add r2 r1 1`, `move r3 r2` becomes `add r3 r1 1` when `r2` is not read afterwards.
+pub struct CopyPropagation;
+
+/// Registers holding a copy of another register, with the operand it was copied from
+type Copies = Vec<(Register, String, Register)>;
+
+/// Copy made by `instr`: the register written, the operand copied and its register
+fn copy(instr: &Instruction, aliases: &Aliases) -> Option<(Register, String, Register)> {
+    match (instr.op.as_str(), instr.args.as_slice()) {
+        ("move", [to, from]) => {
+            let to = aliases.register(to)?;
+            let r = aliases.register(from)?;
+            Some((to, from.clone(), r)).filter(|_| to != r)
+        }
+        _ => None,
+    }
+}
+
+fn transfer(instr: &Instruction, aliases: &Aliases, copies: &mut Copies) {
+    let effects = Effects::of(instr, aliases);
+    if effects.defs_unknown || matches!(instr.op.as_str(), "alias" | "define") {
+        copies.clear();
+        return;
+    }
+    copies
+        .retain(|(to, _, from)| !effects.may_defs.contains(to) && !effects.may_defs.contains(from));
+    copies.extend(copy(instr, aliases));
+}
+
+impl CopyPropagation {
+    /// Read copies in place of the registers holding them
+    fn propagate(program: &mut Program, aliases: &Aliases) -> bool {
+        let cfg = Cfg::build(program);
+        let join = |a: &Copies, b: &Copies| -> Copies {
+            a.iter().filter(|c| b.contains(c)).cloned().collect()
+        };
+        let copies_in = forward(program, &cfg, Copies::new(), join, |i, c| {
+            transfer(i, aliases, c)
+        });
+        let mut changed = false;
+        for (line, copies) in program.lines.iter_mut().zip(copies_in.iter()) {
+            let (instr, copies) = match (line.instruction_mut(), copies) {
+                (Some(instr), Some(copies)) => (instr, copies),
+                _ => continue,
+            };
+            let kinds = match signature(&instr.op).filter(|k| k.len() == instr.args.len()) {
+                Some(kinds) => kinds,
+                None => continue,
+            };
+            for (arg, kind) in instr.args.iter_mut().zip(kinds) {
+                if *kind != ArgKind::Number {
+                    continue;
+                }
+                let r = aliases.register(arg);
+                if let Some((_, from, _)) = copies.iter().find(|(to, _, _)| Some(*to) == r) {
+                    *arg = from.clone();
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Write the result of instructions straight to the register they are copied to
+    fn coalesce(program: &mut Program, aliases: &Aliases) -> bool {
+        let liveness = Liveness::compute(program, &Cfg::build(program));
+        let mut removed = HashSet::new();
+        for i in 1..program.len() {
+            let (def, copied) = match (&program.lines[i - 1], &program.lines[i]) {
+                (Line::Instruction(def), Line::Instruction(copied)) => (def, copied),
+                _ => continue,
+            };
+            let (to, from) = match (copy(copied, aliases), copied.args.first()) {
+                (Some((_, from, r)), Some(to)) if !liveness.is_live_after(i, r) => (to, from),
+                _ => continue,
+            };
+            let writes_from = signature(&def.op).and_then(|k| k.first())
+                == Some(&ArgKind::Register)
+                && def.args.first() == Some(&from)
+                && Effects::of(def, aliases).is_pure();
+            if !writes_from || removed.contains(&(i - 1)) {
+                continue;
+            }
+            let to = to.clone();
+            program.lines[i - 1].instruction_mut().unwrap().args[0] = to;
+            removed.insert(i);
+        }
+        let mut i = 0;
+        program.lines.retain(|_| {
+            i += 1;
+            !removed.contains(&(i - 1))
+        });
+        !removed.is_empty()
+    }
+}
+
+impl Pass for CopyPropagation {
+    fn name(&self) -> &'static str {
+        "copies"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let aliases = Aliases::of(program);
+        let propagated = Self::propagate(program, &aliases);
+        let coalesced = Self::coalesce(program, &aliases);
+        Ok(propagated || coalesced)
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{
+    analysis::{forward, Aliases, Effects, Register, RegisterSet},
+    cfg::Cfg,
+    instruction::{signature, ArgKind},
+    profile::HardwareProfile,
+    program::{Instruction, Line, Program},
+};
+
+use super::Pass;
+
+/// Replaces instructions computing a value some register already holds on every path to
+/// them with a `move` from that register, e.g. the second `mul` of
+/// `mul r1 r0 1.8`, `s d0 Setting r1`, `mul r2 r0 1.8` with `move r2 r1`.
+///
+/// Device loads are left alone, as devices change under the program, except for loads of
+/// the logic types marked non-volatile. Reads of the IC's own stack memory (`get r db a`)
+/// are reused until the stack is written or the IC yields, other devices' memory can
+/// change anytime.
+#[derive(Clone, Debug, Default)]
+pub struct CommonSubexpressions {
+    nonvolatile: Vec<String>,
+}
+
+/// Instructions reading devices, or whether they are connected
+pub(super) const DEVICE_LOADS: &[&str] = &[
+    "l", "lb", "lbn", "lbns", "lbs", "ld", "lr", "ls", "sdse", "sdns",
+];
+/// Instructions writing devices, changing what loads from them read
+const DEVICE_STORES: &[&str] = &["s", "sb", "sbn", "sbs", "sd"];
+/// Instructions after which stack memory may have changed
+const STACK_WRITES: &[&str] = &["push", "put", "yield", "sleep"];
+/// Instructions whose operands can be swapped
+const COMMUTATIVE: &[&str] = &["add", "mul", "max", "min", "and", "or", "xor", "nor"];
+
+/// Value computed by an instruction, all of it but the register it writes
+#[derive(Clone, Debug, PartialEq)]
+struct Expression {
+    op: String,
+    args: Vec<String>,
+    /// Registers read
+    reads: RegisterSet,
+}
+
+impl Expression {
+    fn is_device_load(&self) -> bool {
+        DEVICE_LOADS.contains(&self.op.as_str())
+    }
+
+    fn is_stack_load(&self) -> bool {
+        self.op == "get"
+    }
+}
+
+/// Expressions available in registers
+type Available = Vec<(Expression, Register)>;
+
+impl CommonSubexpressions {
+    /// Treat loads of the logic types `nonvolatile` as reading the same value until the
+    /// program stores to a device
+    pub fn new(nonvolatile: Vec<String>) -> Self {
+        Self { nonvolatile }
+    }
+
+    /// Expression computed by `instr` and the register it is written to, if the
+    /// instruction does nothing else
+    fn expression(&self, instr: &Instruction, aliases: &Aliases) -> Option<(Expression, Register)> {
+        let kinds = signature(&instr.op).filter(|k| k.len() == instr.args.len())?;
+        if kinds.first() != Some(&ArgKind::Register) || instr.op == "move" {
+            return None;
+        }
+        let effects = Effects::of(instr, aliases);
+        let r = aliases.register(&instr.args[0])?;
+        if !effects.is_pure() || effects.uses_unknown || effects.defs.len() != 1 {
+            return None;
+        }
+        let mut args = instr.args[1..].to_vec();
+        if DEVICE_LOADS.contains(&instr.op.as_str())
+            && !args.iter().any(|a| self.nonvolatile.contains(a))
+        {
+            return None;
+        }
+        if instr.op == "get" && args.first().map(String::as_str) != Some("db") {
+            return None;
+        }
+        if COMMUTATIVE.contains(&instr.op.as_str()) {
+            args.sort();
+        }
+        let expr = Expression {
+            op: instr.op.clone(),
+            args,
+            reads: effects.uses,
+        };
+        Some((expr, r))
+    }
+
+    /// Update `available` past instruction `instr`
+    fn transfer(&self, instr: &Instruction, aliases: &Aliases, available: &mut Available) {
+        let effects = Effects::of(instr, aliases);
+        let op = instr.op.as_str();
+        if effects.defs_unknown || matches!(op, "alias" | "define") {
+            available.clear();
+            return;
+        }
+        let stores = DEVICE_STORES.contains(&op);
+        let writes_stack = STACK_WRITES.contains(&op);
+        available.retain(|(expr, r)| {
+            let overwritten =
+                effects.may_defs.contains(r) || !expr.reads.is_disjoint(&effects.may_defs);
+            let changed =
+                (expr.is_device_load() && stores) || (expr.is_stack_load() && writes_stack);
+            !overwritten && !changed
+        });
+        if let Some((expr, r)) = self.expression(instr, aliases) {
+            // `add r0 r0 1` leaves `r0` holding something else than `r0 + 1`
+            if !expr.reads.contains(&r) {
+                available.push((expr, r));
+            }
+        }
+    }
+}
+
+impl Pass for CommonSubexpressions {
+    fn name(&self) -> &'static str {
+        "cse"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let aliases = Aliases::of(program);
+        let cfg = Cfg::build(program);
+        let join = |a: &Available, b: &Available| -> Available {
+            a.iter().filter(|e| b.contains(e)).cloned().collect()
+        };
+        let available_in = forward(program, &cfg, Available::new(), join, |i, a| {
+            self.transfer(i, &aliases, a)
+        });
+        let mut changed = false;
+        for (line, available) in program.lines.iter_mut().zip(available_in.iter()) {
+            let (instr, available) = match (line.instruction(), available) {
+                (Some(instr), Some(available)) => (instr, available),
+                _ => continue,
+            };
+            let (expr, r) = match self.expression(instr, &aliases) {
+                Some(e) => e,
+                None => continue,
+            };
+            let holder = match available.iter().find(|(e, _)| *e == expr) {
+                Some((_, holder)) => *holder,
+                None => continue,
+            };
+            *line = if holder == r {
+                Line::Empty
+            } else {
+                let dest = instr.args[0].clone();
+                Line::Instruction(Instruction::new("move", &[&dest, &aliases.name(holder)]))
+            };
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
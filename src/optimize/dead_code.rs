@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+
+use petgraph::visit::Dfs;
+
+use crate::{
+    analysis::{Aliases, Effects, Liveness},
+    cfg::Cfg,
+    profile::HardwareProfile,
+    program::{Line, Program},
+};
+
+use super::Pass;
+
+/// Removes lines that cannot change what the program does:
+///
+/// * blocks unreachable from the first line (labels still referenced aside),
+/// * instructions only writing registers that are never read afterwards,
+/// * labels nothing refers to, and blank or comment lines.
+pub struct DeadCode;
+
+impl DeadCode {
+    /// Lines that are not reachable from the first line
+    fn unreachable(program: &Program, cfg: &Cfg) -> HashSet<usize> {
+        let mut reachable = HashSet::new();
+        if let Some(entry) = cfg.entry() {
+            let mut dfs = Dfs::new(cfg.graph(), entry);
+            while let Some(node) = dfs.next(cfg.graph()) {
+                reachable.insert(node);
+            }
+        }
+        cfg.blocks()
+            .filter(|(node, _)| !reachable.contains(node))
+            .flat_map(|(_, block)| block.lines())
+            .filter(|l| !matches!(program.lines[*l], Line::Label(_)))
+            .collect()
+    }
+
+    /// Instructions whose results are never read
+    fn unused(program: &Program, cfg: &Cfg) -> HashSet<usize> {
+        let aliases = Aliases::of(program);
+        let liveness = Liveness::compute(program, cfg);
+        program
+            .instructions()
+            .filter(|(i, instr)| {
+                let effects = Effects::of(instr, &aliases);
+                effects.is_pure()
+                    && !effects.defs.is_empty()
+                    && effects.defs.iter().all(|r| !liveness.is_live_after(*i, *r))
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+impl Pass for DeadCode {
+    fn name(&self) -> &'static str {
+        "dead-code"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let cfg = Cfg::build(program);
+        let mut dead = Self::unreachable(program, &cfg);
+        dead.extend(Self::unused(program, &cfg));
+        for (i, line) in program.lines.iter().enumerate() {
+            match line {
+                Line::Empty => {
+                    dead.insert(i);
+                }
+                Line::Label(l) if program.references(l).is_empty() => {
+                    dead.insert(i);
+                }
+                _ => {}
+            }
+        }
+        if dead.is_empty() {
+            return Ok(false);
+        }
+        let mut i = 0;
+        program.lines.retain(|_| {
+            i += 1;
+            !dead.contains(&(i - 1))
+        });
+        Ok(true)
+    }
+}
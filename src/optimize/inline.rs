@@ -0,0 +1,187 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use crate::{
+    analysis::{Aliases, Effects, Liveness, Register},
+    cfg::Cfg,
+    profile::HardwareProfile,
+    program::{fresh_name, Instruction, Line, Program},
+};
+
+use super::{size, Pass};
+
+/// Replaces `jal f` calls with the body of subroutine `f` (from its label to its `j ra`),
+/// then removes the subroutine.
+///
+/// Subroutines are inlined when they have a single caller, or when the inlined program
+/// still fits the hardware profile. Inlining saves running the `jal`, label and `j ra`
+/// lines on every call.
+///
+/// Subroutines reading `ra`, or returning to code reading it, are left alone: inlined,
+/// `ra` would have to hold the return address as a label, keeping labels from being
+/// resolved to line numbers.
+pub struct InlineSubroutines;
+
+/// Subroutine body, lines `label + 1..ret` ending at the `j ra` on line `ret`
+struct Subroutine {
+    label: usize,
+    ret: usize,
+    /// Labels inside the body, only referenced from the body
+    labels: Vec<String>,
+    /// Lines of the `jal` calls
+    calls: Vec<usize>,
+}
+
+impl Subroutine {
+    fn find(program: &Program, name: &str, label: usize) -> Option<Self> {
+        let calls = program.references(name);
+        let is_call = |i: &usize| {
+            let instr = program.lines[*i].instruction().unwrap();
+            instr.op == "jal" && instr.args.len() == 1
+        };
+        if calls.is_empty() || !calls.iter().all(is_call) {
+            return None;
+        }
+        // Only entered through calls, never by running into the label
+        let previous = program.lines[..label]
+            .iter()
+            .rev()
+            .find(|l| **l != Line::Empty)?;
+        match previous.instruction().map(|i| i.op.as_str()) {
+            Some("j") | Some("jr") => {}
+            _ => return None,
+        }
+
+        let aliases = Aliases::of(program);
+        let mut labels = Vec::new();
+        for (i, line) in program.lines.iter().enumerate().skip(label + 1) {
+            let instr = match line {
+                Line::Empty => continue,
+                Line::Label(l) => {
+                    labels.push(l.clone());
+                    continue;
+                }
+                Line::Instruction(instr) => instr,
+            };
+            if instr.op == "j" && instr.args == ["ra"] {
+                let inside = |l: &usize| *l > label && *l < i;
+                let internal = labels
+                    .iter()
+                    .all(|l| program.references(l).iter().all(inside));
+                // Branches in the body must stay in the body
+                let contained = program.lines[label + 1..i]
+                    .iter()
+                    .filter_map(|l| l.instruction().and_then(Instruction::target))
+                    .all(|t| labels.iter().any(|l| l == t));
+                if !internal || !contained || calls.iter().any(inside) {
+                    return None;
+                }
+                return Some(Self {
+                    label,
+                    ret: i,
+                    labels,
+                    calls,
+                });
+            }
+            let effects = Effects::of(instr, &aliases);
+            // The body reading `ra` needs it to hold the return address, a label
+            if effects.defs_unknown
+                || effects.may_defs.contains(&Register::Ra)
+                || effects.uses_unknown
+                || effects.uses.contains(&Register::Ra)
+                || instr.target() == Some("ra")
+            {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Body copied for the call on line `call`, with internal labels renamed to names
+    /// that are not `taken`
+    fn body<F: Fn(&str) -> bool>(&self, program: &Program, call: usize, taken: F) -> Vec<Line> {
+        let mut renamed: HashMap<&str, String> = HashMap::new();
+        for l in self.labels.iter() {
+            let name = fresh_name(&format!("{}_{}", l, call), |n| {
+                taken(n) || renamed.values().any(|r| r == n)
+            });
+            renamed.insert(l, name);
+        }
+        let mut body = Vec::new();
+        for line in program.lines[self.label + 1..self.ret].iter() {
+            match line {
+                Line::Empty => {}
+                Line::Label(l) => body.push(Line::Label(renamed[l.as_str()].clone())),
+                Line::Instruction(instr) => {
+                    let mut instr = instr.clone();
+                    for a in instr.args.iter_mut() {
+                        if let Some(l) = renamed.get(a.as_str()) {
+                            *a = l.clone();
+                        }
+                    }
+                    body.push(Line::Instruction(instr));
+                }
+            }
+        }
+        body
+    }
+
+    /// Program with every call replaced by the body, and the subroutine removed. `None`
+    /// when `ra` is read after a call, as it would have to hold a label.
+    fn inline(&self, program: &Program) -> Option<Program> {
+        let liveness = Liveness::compute(program, &Cfg::build(program));
+        let mut names: HashSet<String> = HashSet::new();
+        let mut edits: Vec<(Range<usize>, Vec<Line>)> = vec![(self.label..self.ret + 1, vec![])];
+        for call in self.calls.iter().cloned() {
+            let reads_ra = liveness
+                .live_in
+                .get(call + 1)
+                .is_some_and(|regs| regs.contains(&Register::Ra));
+            if reads_ra {
+                return None;
+            }
+            let taken = |n: &str| program.is_name_taken(n) || names.contains(n);
+            let body = self.body(program, call, taken);
+            names.extend(body.iter().filter_map(|l| match l {
+                Line::Label(l) => Some(l.clone()),
+                _ => None,
+            }));
+            edits.push((call..call + 1, body));
+        }
+        // Edit from the last line up, so earlier line indices stay valid
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        let mut result = program.clone();
+        for (range, lines) in edits {
+            result.lines.splice(range, lines);
+        }
+        Some(result)
+    }
+}
+
+impl Pass for InlineSubroutines {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn run(&self, program: &mut Program, profile: &HardwareProfile) -> Result<bool, String> {
+        let mut labels: Vec<(String, usize)> = program.labels().into_iter().collect();
+        labels.sort_by_key(|(_, i)| *i);
+        for (name, label) in labels {
+            let sub = match Subroutine::find(program, &name, label) {
+                Some(sub) => sub,
+                None => continue,
+            };
+            let inlined = match sub.inline(program) {
+                Some(inlined) => inlined,
+                None => continue,
+            };
+            if sub.calls.len() == 1 || size(&inlined, profile).fits() {
+                *program = inlined;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    instruction::signature,
+    profile::HardwareProfile,
+    program::{Line, Program},
+};
+
+use super::Pass;
+
+/// Simplifies jumps:
+///
+/// * jumps and branches to a `j` go straight to its target, so chains of jumps collapse
+///   into a single hop,
+/// * jumps and branches to the next line, which do nothing, are removed,
+/// * a branch over a `j` is inverted to branch to the `j`'s target instead:
+///   `beq a b L1`, `j L2`, `L1:` becomes `bne a b L2`, `L1:`. Branches on ordered
+///   comparisons (`blt`, ...) have no inverse, as they are all false for NaN operands.
+pub struct SimplifyJumps;
+
+/// Index of the first instruction from line `line`, skipping labels and blank lines
+fn next_instruction(program: &Program, line: usize) -> Option<usize> {
+    (line..program.len()).find(|i| program.lines[*i].instruction().is_some())
+}
+
+/// Whether only labels and blank lines are between lines `from` and `to`
+fn falls_through(program: &Program, from: usize, to: usize) -> bool {
+    from < to
+        && program.lines[from + 1..to]
+            .iter()
+            .all(|l| l.instruction().is_none())
+}
+
+impl SimplifyJumps {
+    /// Label the `j` at label `label` jumps to
+    fn jump_at(program: &Program, labels: &HashMap<String, usize>, label: &str) -> Option<String> {
+        let instr = program.lines[next_instruction(program, labels[label])?].instruction()?;
+        match (instr.op.as_str(), instr.args.as_slice()) {
+            ("j", [target]) if labels.contains_key(target) => Some(target.clone()),
+            _ => None,
+        }
+    }
+
+    /// Retarget jumps and branches to `j`s. Returns whether anything changed.
+    fn thread(program: &mut Program, labels: &HashMap<String, usize>) -> bool {
+        let mut changed = false;
+        for i in 0..program.len() {
+            let label = match program.lines[i].instruction().and_then(|i| i.target()) {
+                Some(l) if labels.contains_key(l) => l.to_owned(),
+                _ => continue,
+            };
+            let mut visited = HashSet::new();
+            let mut target = label.clone();
+            visited.insert(target.clone());
+            while let Some(next) = Self::jump_at(program, labels, &target) {
+                // Jumps going round in circles are left alone
+                if !visited.insert(next.clone()) {
+                    break;
+                }
+                target = next;
+            }
+            if target != label {
+                let instr = program.lines[i].instruction_mut().unwrap();
+                *instr.args.last_mut().unwrap() = target;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+impl Pass for SimplifyJumps {
+    fn name(&self) -> &'static str {
+        "jumps"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let labels = program.labels();
+        let mut changed = Self::thread(program, &labels);
+
+        // Line of the label a (non-linking) branch on line `i` goes to
+        let target = |program: &Program, i: usize| {
+            let instr = program.lines[i].instruction()?;
+            instr
+                .branch()
+                .filter(|b| !b.link && !b.relative)
+                .and_then(|_| labels.get(instr.target()?).cloned())
+        };
+        let mut removed = HashSet::new();
+        for i in 0..program.len() {
+            if target(program, i).is_some_and(|t| falls_through(program, i, t)) {
+                removed.insert(i);
+            }
+        }
+        for i in 0..program.len().saturating_sub(1) {
+            let over = match target(program, i) {
+                Some(t) if !removed.contains(&i) && !removed.contains(&(i + 1)) => t,
+                _ => continue,
+            };
+            let (branch, jump) = match (&program.lines[i], &program.lines[i + 1]) {
+                (Line::Instruction(b), Line::Instruction(j))
+                    if j.op == "j" && j.args.len() == 1 =>
+                {
+                    (b, &j.args[0])
+                }
+                _ => continue,
+            };
+            let mut inverted = match branch.branch() {
+                Some(b) if b.is_conditional() && falls_through(program, i + 1, over) => b,
+                _ => continue,
+            };
+            inverted.condition = match inverted.condition.and_then(|c| c.inverse()) {
+                Some(c) => Some(c),
+                None => continue,
+            };
+            let op = inverted.op();
+            if signature(&op).is_none() || !labels.contains_key(jump) {
+                continue;
+            }
+            let mut instr = branch.clone();
+            instr.op = op;
+            *instr.args.last_mut().unwrap() = jump.clone();
+            program.lines[i] = Line::Instruction(instr);
+            removed.insert(i + 1);
+        }
+
+        changed |= !removed.is_empty();
+        let mut i = 0;
+        program.lines.retain(|_| {
+            i += 1;
+            !removed.contains(&(i - 1))
+        });
+        Ok(changed)
+    }
+}
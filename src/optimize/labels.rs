@@ -0,0 +1,77 @@
+use crate::{
+    cfg::{resolve_target, Target},
+    profile::HardwareProfile,
+    program::{Line, Program},
+};
+
+use super::Pass;
+
+/// Removes label lines, jumping to absolute line numbers instead, which saves a line (and
+/// an executed instruction) per label.
+///
+/// Programs using the value of a label other than as a jump target, as in `move r0 loop`,
+/// compute with line numbers, which removing lines would change: they are left as they
+/// are. Since lines cannot be added or removed afterwards, this runs once, after every
+/// other pass.
+pub struct ResolveLabels;
+
+impl ResolveLabels {
+    /// Whether a label is used as a value rather than a jump target
+    fn uses_values(program: &Program) -> bool {
+        let labels = program.labels();
+        program.instructions().any(|(_, instr)| {
+            let nargs = match instr.branch() {
+                Some(_) => instr.args.len().saturating_sub(1),
+                None => instr.args.len(),
+            };
+            instr.args[..nargs].iter().any(|a| labels.contains_key(a))
+        })
+    }
+}
+
+impl Pass for ResolveLabels {
+    fn name(&self) -> &'static str {
+        "labels"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        if Self::uses_values(program) {
+            return Ok(false);
+        }
+        let removed = |line: &Line| matches!(line, Line::Label(_));
+        // Line number of every line once labels are removed, removed labels taking the
+        // number of the line after them
+        let mut renumbered = Vec::with_capacity(program.len() + 1);
+        let mut n = 0;
+        for line in program.lines.iter() {
+            renumbered.push(n);
+            if !removed(line) {
+                n += 1;
+            }
+        }
+        renumbered.push(n);
+
+        let mut result = program.clone();
+        for (i, instr) in program.instructions() {
+            let target = match resolve_target(program, i, instr) {
+                Some(Target::Line(l)) => renumbered[l.min(program.len())],
+                Some(Target::Indirect) => {
+                    return Err(format!(
+                        "Line {} jumps to a target computed at runtime, lines cannot be moved",
+                        i
+                    ))
+                }
+                _ => continue,
+            };
+            let mut branch = instr.branch().unwrap();
+            branch.relative = false;
+            let instr = result.lines[i].instruction_mut().unwrap();
+            instr.op = branch.op();
+            *instr.args.last_mut().unwrap() = target.to_string();
+        }
+        result.lines.retain(|l| !removed(l));
+        let changed = result != *program;
+        *program = result;
+        Ok(changed)
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{
+    cfg::Cfg,
+    profile::HardwareProfile,
+    program::{Instruction, Line, Program},
+};
+
+use super::Pass;
+
+/// Reorders basic blocks so that blocks ending in `j L` are followed by the block at `L`,
+/// whose jump is then dropped.
+///
+/// Blocks are chained to the block they fall through to (which includes returning from a
+/// call), then to the block they jump to when that block starts no chain yet. Chains are
+/// laid out in source order, the first line's first and the one running off the end of
+/// the program last. The new layout is only kept when it has fewer instructions.
+///
+/// Chains are not ordered hot path first, as there is no execution profile to order them
+/// by: line counts of a run are of the script as written, which the passes before this
+/// one rewrite.
+pub struct BlockLayout;
+
+/// Block `start..end` of the program, with how it is left
+struct Block {
+    start: usize,
+    end: usize,
+    /// Continues into the next block (or off the end of the program)
+    falls_through: bool,
+    /// Block its final `j` goes to
+    jumps_to: Option<usize>,
+}
+
+impl BlockLayout {
+    fn blocks(program: &Program) -> Vec<Block> {
+        let cfg = Cfg::build(program);
+        let labels = program.labels();
+        let mut bounds: Vec<(usize, usize)> = cfg.blocks().map(|(_, b)| (b.start, b.end)).collect();
+        bounds.sort();
+        let block_of = |line: usize| bounds.iter().position(|(s, e)| (*s..*e).contains(&line));
+        bounds
+            .iter()
+            .map(|(start, end)| {
+                let jump = program.lines[end - 1]
+                    .instruction()
+                    .filter(|i| i.op == "j" && i.args.len() == 1);
+                Block {
+                    start: *start,
+                    end: *end,
+                    falls_through: jump.is_none(),
+                    jumps_to: jump
+                        .and_then(|j| labels.get(&j.args[0]))
+                        .and_then(|l| block_of(*l)),
+                }
+            })
+            .collect()
+    }
+
+    /// Block order, and the block following each block in it
+    fn order(blocks: &[Block]) -> (Vec<usize>, Vec<Option<usize>>) {
+        let n = blocks.len();
+        let mut next: Vec<Option<usize>> = vec![None; n];
+        let mut prev: Vec<Option<usize>> = vec![None; n];
+        let head = |prev: &[Option<usize>], mut b: usize| {
+            while let Some(p) = prev[b] {
+                b = p;
+            }
+            b
+        };
+        for b in 0..n.saturating_sub(1) {
+            if blocks[b].falls_through {
+                next[b] = Some(b + 1);
+                prev[b + 1] = Some(b);
+            }
+        }
+        for b in 0..n {
+            // The first block must stay first
+            if let Some(t) = blocks[b].jumps_to.filter(|t| *t != 0) {
+                if next[b].is_none() && prev[t].is_none() && head(&prev, b) != t {
+                    next[b] = Some(t);
+                    prev[t] = Some(b);
+                }
+            }
+        }
+
+        let mut heads: Vec<usize> = (0..n).filter(|b| prev[*b].is_none()).collect();
+        if let Some(last) = (0..n).last().filter(|b| blocks[*b].falls_through) {
+            let h = head(&prev, last);
+            if h != 0 {
+                heads.retain(|b| *b != h);
+                heads.push(h);
+            }
+        }
+        let mut order = Vec::with_capacity(n);
+        for h in heads {
+            let mut b = Some(h);
+            while let Some(block) = b {
+                order.push(block);
+                b = next[block];
+            }
+        }
+        (order, next)
+    }
+}
+
+impl Pass for BlockLayout {
+    fn name(&self) -> &'static str {
+        "layout"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let blocks = Self::blocks(program);
+        let (order, next) = Self::order(&blocks);
+        if order.iter().enumerate().all(|(i, b)| i == *b) {
+            return Ok(false);
+        }
+        let last = blocks.len() - 1;
+        let end = program.fresh_name("end");
+        let mut lines = Vec::with_capacity(program.len());
+        let mut needs_end = false;
+        for (i, b) in order.iter().enumerate() {
+            let block = &blocks[*b];
+            let following = order.get(i + 1).cloned();
+            let mut block_lines = &program.lines[block.start..block.end];
+            if block.jumps_to.is_some() && following.is_some() && following == next[*b] {
+                block_lines = &block_lines[..block_lines.len() - 1];
+            }
+            lines.extend(block_lines.iter().cloned());
+            if *b == last && block.falls_through && following.is_some() {
+                lines.push(Line::Instruction(Instruction::new("j", &[&end])));
+                needs_end = true;
+            }
+        }
+        if needs_end {
+            lines.push(Line::Label(end));
+        }
+
+        let count = |lines: &[Line]| lines.iter().filter(|l| l.instruction().is_some()).count();
+        if count(&lines) >= count(&program.lines) {
+            return Ok(false);
+        }
+        program.lines = lines;
+        Ok(true)
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use petgraph::{algo::dominators::simple_fast, graph::NodeIndex};
+
+use crate::{
+    analysis::{Aliases, Effects, Liveness, Register},
+    cfg::{Cfg, EdgeKind},
+    profile::HardwareProfile,
+    program::{Instruction, Line, Program},
+};
+
+use super::{cse::DEVICE_LOADS, Pass};
+
+/// Moves instructions computing the same value on every iteration of a loop out of it,
+/// in front of its first line, so that they run once instead of every iteration.
+///
+/// Loops are found from the back edges of the control-flow graph, and must only be
+/// entered by running into their first line (or by starting the program there), which is
+/// how main loops such as `start: yield ... j start` look. Registers keep their values
+/// across `yield`, so hoisted values are still there on the next tick. Device loads
+/// (`sdse`/`sdns` included, devices may be unplugged), stack reads and `rand` are never
+/// hoisted, they may give another value every iteration.
+pub struct HoistInvariants;
+
+/// Blocks of a natural loop, entered through `header`
+struct Loop {
+    header: NodeIndex,
+    body: HashSet<NodeIndex>,
+}
+
+impl Loop {
+    /// Loops of the program, one per header
+    fn find(cfg: &Cfg) -> Vec<Self> {
+        let entry = match cfg.entry() {
+            Some(entry) => entry,
+            None => return Vec::new(),
+        };
+        let dominators = simple_fast(cfg.graph(), entry);
+        let mut loops: HashMap<NodeIndex, HashSet<NodeIndex>> = HashMap::new();
+        for (node, _) in cfg.blocks() {
+            for (header, kind) in cfg.successors(node) {
+                let back = matches!(kind, EdgeKind::FallThrough | EdgeKind::Branch)
+                    && dominators
+                        .dominators(node)
+                        .is_some_and(|mut d| d.any(|n| n == header));
+                if !back {
+                    continue;
+                }
+                // Blocks reaching the back edge without going through the header
+                let body = loops.entry(header).or_insert_with(|| {
+                    let mut body = HashSet::new();
+                    body.insert(header);
+                    body
+                });
+                let mut worklist = vec![node];
+                while let Some(n) = worklist.pop() {
+                    if body.insert(n) {
+                        worklist.extend(cfg.predecessors(n).iter().map(|(p, _)| *p));
+                    }
+                }
+            }
+        }
+        let mut loops: Vec<Self> = loops
+            .into_iter()
+            .map(|(header, body)| Self { header, body })
+            .collect();
+        // Inner loops first
+        loops.sort_by_key(|l| (l.body.len(), l.header.index()));
+        loops
+    }
+
+    /// Whether the loop is only entered by running into its header
+    fn has_preheader(&self, cfg: &Cfg) -> bool {
+        let start = cfg.block(self.header).start;
+        cfg.predecessors(self.header)
+            .iter()
+            .filter(|(p, _)| !self.body.contains(p))
+            .all(|(p, kind)| *kind == EdgeKind::FallThrough && cfg.block(*p).end == start)
+    }
+
+    fn lines(&self, cfg: &Cfg) -> Vec<usize> {
+        let mut lines: Vec<usize> = self
+            .body
+            .iter()
+            .flat_map(|n| cfg.block(*n).lines())
+            .collect();
+        lines.sort();
+        lines
+    }
+
+    /// Lines computing the same value on every iteration, in the order they can run in
+    fn invariants(&self, program: &Program, cfg: &Cfg, liveness: &Liveness) -> Vec<usize> {
+        let aliases = Aliases::of(program);
+        let lines = self.lines(cfg);
+        let effects: Vec<(usize, &Instruction, Effects)> = lines
+            .iter()
+            .filter_map(|l| program.lines[*l].instruction().map(|i| (*l, i)))
+            .map(|(l, i)| (l, i, Effects::of(i, &aliases)))
+            .collect();
+        if effects.iter().any(|(_, _, e)| e.defs_unknown) {
+            return Vec::new();
+        }
+        // Registers that may hold a value computed before the loop, or after leaving it
+        let mut live: BTreeSet<Register> = liveness.live_in[cfg.block(self.header).start].clone();
+        for node in self.body.iter() {
+            for (succ, _) in cfg.successors(*node) {
+                if !self.body.contains(&succ) {
+                    live.extend(liveness.live_in[cfg.block(succ).start].iter().cloned());
+                }
+            }
+        }
+
+        let mut hoisted: Vec<usize> = Vec::new();
+        loop {
+            // Registers written in the loop by lines staying in it
+            let written: Vec<Register> = effects
+                .iter()
+                .filter(|(l, _, _)| !hoisted.contains(l))
+                .flat_map(|(_, _, e)| e.may_defs.iter().cloned())
+                .collect();
+            let invariant = effects.iter().find(|(l, instr, e)| {
+                let op = instr.op.as_str();
+                let r = match e.defs.iter().next() {
+                    Some(r) if e.defs.len() == 1 => r,
+                    _ => return false,
+                };
+                !hoisted.contains(l)
+                    && e.is_pure()
+                    && !e.uses_unknown
+                    && !DEVICE_LOADS.contains(&op)
+                    && op != "get"
+                    && written.iter().filter(|w| *w == r).count() == 1
+                    && !live.contains(r)
+                    && e.uses.iter().all(|u| !written.contains(u))
+            });
+            match invariant {
+                Some((l, _, _)) => hoisted.push(*l),
+                None => return hoisted,
+            }
+        }
+    }
+}
+
+impl Pass for HoistInvariants {
+    fn name(&self) -> &'static str {
+        "licm"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let cfg = Cfg::build(program);
+        let liveness = Liveness::compute(program, &cfg);
+        for l in Loop::find(&cfg) {
+            if !l.has_preheader(&cfg) {
+                continue;
+            }
+            let hoisted = l.invariants(program, &cfg, &liveness);
+            if hoisted.is_empty() {
+                continue;
+            }
+            let preheader = cfg.block(l.header).start;
+            let mut lines: Vec<Line> = Vec::with_capacity(program.len());
+            for (i, line) in program.lines.iter().enumerate() {
+                if i == preheader {
+                    lines.extend(hoisted.iter().map(|h| program.lines[*h].clone()));
+                }
+                if !hoisted.contains(&i) {
+                    lines.push(line.clone());
+                }
+            }
+            program.lines = lines;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
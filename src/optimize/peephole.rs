@@ -0,0 +1,269 @@
+use std::{collections::HashMap, fs::read_to_string, ops::Range};
+
+use crate::{
+    analysis::{Aliases, Effects, Liveness, Register},
+    cfg::Cfg,
+    instruction::signature,
+    profile::HardwareProfile,
+    program::{Instruction, Line, Program},
+};
+
+use super::Pass;
+
+/// Rewrites sequences of instructions matching the patterns of rules, read from a rules
+/// file so rules can be added without rebuilding.
+///
+/// One rule per line, `#` starts a comment. A rule is a pattern of `;` separated
+/// instructions, `=>`, the replacement instructions (none to remove the pattern) and
+/// optionally `when` and `,` separated conditions:
+///
+/// ```text
+/// slt $t $a $b; bnez $t $L => blt $a $b $L when $t dead
+/// ```
+///
+/// `$name` operands match any operand, the same one wherever the name appears. Conditions:
+///
+/// * `$t dead`: `$t` is a register that is not read after the pattern
+///
+/// Registers written by the pattern but not by the replacement (or the other way around)
+/// must not be read after the pattern either, whether the rule says so or not, so that
+/// rules cannot lose values still needed. Patterns only match consecutive instructions,
+/// never across labels. Only the last instruction of a pattern or replacement can branch,
+/// as that is where liveness is checked.
+#[derive(Clone, Debug, Default)]
+pub struct Peephole {
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    pattern: Vec<Instruction>,
+    replacement: Vec<Instruction>,
+    /// Captures that must be registers not read after the pattern
+    dead: Vec<String>,
+}
+
+/// Operands matched by the captures of a pattern
+type Captures<'a> = HashMap<&'a str, &'a str>;
+
+fn is_capture(token: &str) -> bool {
+    token.starts_with('$')
+}
+
+/// `;` separated instructions
+fn parse_instructions(s: &str) -> Result<Vec<Instruction>, String> {
+    let mut instrs = Vec::new();
+    for instr in s.split(';').map(str::trim).filter(|i| !i.is_empty()) {
+        let tokens: Vec<&str> = instr.split_whitespace().collect();
+        let (op, args) = (tokens[0], &tokens[1..]);
+        let arity = signature(op)
+            .ok_or_else(|| format!("Unknown instruction '{}'", op))?
+            .len();
+        if args.len() != arity {
+            return Err(format!(
+                "'{}' takes {} arguments, not {}",
+                op,
+                arity,
+                args.len()
+            ));
+        }
+        let instr = Instruction::new(op, args);
+        if let Some(previous) = instrs
+            .last()
+            .filter(|i: &&Instruction| i.branch().is_some())
+        {
+            return Err(format!(
+                "'{}' branches before the last instruction",
+                previous
+            ));
+        }
+        instrs.push(instr);
+    }
+    Ok(instrs)
+}
+
+/// `$t dead` condition, the capture that must be dead
+fn parse_dead(condition: &str) -> Result<String, String> {
+    match condition.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [t, "dead"] if is_capture(t) => Ok((*t).to_owned()),
+        _ => Err(format!("Unknown condition '{}'", condition)),
+    }
+}
+
+impl Rule {
+    fn parse(s: &str) -> Result<Self, String> {
+        let (pattern, rest) = s.split_once("=>").ok_or("Missing '=>'")?;
+        let (replacement, conditions) = rest.split_once(" when ").unwrap_or((rest, ""));
+        let rule = Self {
+            pattern: parse_instructions(pattern)?,
+            replacement: parse_instructions(replacement)?,
+            dead: conditions
+                .split(',')
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .map(parse_dead)
+                .collect::<Result<_, String>>()?,
+        };
+        if rule.pattern.is_empty() {
+            return Err("Empty pattern".to_owned());
+        }
+        let captured = |t: &&String| rule.pattern.iter().any(|i| i.args.iter().any(|a| a == *t));
+        let used = rule
+            .replacement
+            .iter()
+            .flat_map(|i| i.args.iter())
+            .chain(rule.dead.iter());
+        if let Some(t) = used.filter(|t| is_capture(t)).find(|t| !captured(t)) {
+            return Err(format!("'{}' is not in the pattern", t));
+        }
+        Ok(rule)
+    }
+
+    /// Captures if the pattern matches the instructions
+    fn matches<'a>(&'a self, instrs: &[&'a Instruction]) -> Option<Captures<'a>> {
+        let mut captures = Captures::new();
+        for (pattern, instr) in self.pattern.iter().zip(instrs) {
+            if pattern.op != instr.op || pattern.args.len() != instr.args.len() {
+                return None;
+            }
+            for (p, a) in pattern.args.iter().zip(instr.args.iter()) {
+                if is_capture(p) {
+                    if *captures.entry(p).or_insert(a) != a {
+                        return None;
+                    }
+                } else if p != a {
+                    return None;
+                }
+            }
+        }
+        Some(captures)
+    }
+
+    fn replace(&self, captures: &Captures) -> Vec<Line> {
+        self.replacement
+            .iter()
+            .map(|i| {
+                let args: Vec<&str> = i
+                    .args
+                    .iter()
+                    .map(|a| captures.get(a.as_str()).copied().unwrap_or(a))
+                    .collect();
+                Line::Instruction(Instruction::new(&i.op, &args))
+            })
+            .collect()
+    }
+}
+
+impl Peephole {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let rules = s
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or("").trim())
+            .filter(|l| !l.is_empty())
+            .map(|l| Rule::parse(l).map_err(|e| format!("Invalid rule '{}': {}", l, e)))
+            .collect::<Result<_, String>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let s = read_to_string(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        Self::parse(&s)
+    }
+
+    /// Replacement of the lines from `start` by the first rule matching them, with the
+    /// number of lines replaced
+    fn find(
+        &self,
+        program: &Program,
+        start: usize,
+        aliases: &Aliases,
+        liveness: &Liveness,
+    ) -> Option<(usize, Vec<Line>)> {
+        let instrs: Vec<&Instruction> = program.lines[start..]
+            .iter()
+            .map_while(Line::instruction)
+            .collect();
+        self.rules
+            .iter()
+            .filter(|r| r.pattern.len() <= instrs.len())
+            .find_map(|r| {
+                let captures = r.matches(&instrs)?;
+                let last = start + r.pattern.len() - 1;
+                let dead = r.dead.iter().all(|t| {
+                    aliases
+                        .register(captures[t.as_str()])
+                        .is_some_and(|reg| !liveness.is_live_after(last, reg))
+                });
+                let replacement = r.replace(&captures);
+                let matched = &instrs[..r.pattern.len()];
+                let keeps_live = keeps_live(matched, &replacement, aliases, |reg| {
+                    liveness.is_live_after(last, reg)
+                });
+                Some((r.pattern.len(), replacement)).filter(|_| dead && keeps_live)
+            })
+    }
+}
+
+/// Whether replacing `matched` with `replacement` leaves every register still read
+/// (`is_live`) afterwards as it was: registers written by only one of them must not be.
+/// This holds whatever the `dead` conditions of the rule say.
+fn keeps_live<F>(
+    matched: &[&Instruction],
+    replacement: &[Line],
+    aliases: &Aliases,
+    is_live: F,
+) -> bool
+where
+    F: Fn(Register) -> bool,
+{
+    let effects = |instrs: &mut dyn Iterator<Item = &Instruction>| {
+        instrs.fold(Effects::default(), |mut all, i| {
+            let e = Effects::of(i, aliases);
+            all.defs.extend(e.defs);
+            all.may_defs.extend(e.may_defs);
+            all.defs_unknown |= e.defs_unknown;
+            all
+        })
+    };
+    let before = effects(&mut matched.iter().copied());
+    let after = effects(&mut replacement.iter().filter_map(Line::instruction));
+    if before.defs_unknown || after.defs_unknown {
+        return false;
+    }
+    let dropped = before.may_defs.difference(&after.defs);
+    let added = after.may_defs.difference(&before.defs);
+    dropped.chain(added).all(|r| !is_live(*r))
+}
+
+impl Pass for Peephole {
+    fn name(&self) -> &'static str {
+        "peephole"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        if self.rules.is_empty() {
+            return Ok(false);
+        }
+        let aliases = Aliases::of(program);
+        let liveness = Liveness::compute(program, &Cfg::build(program));
+        let mut edits: Vec<(Range<usize>, Vec<Line>)> = Vec::new();
+        let mut i = 0;
+        while i < program.len() {
+            match self.find(program, i, &aliases, &liveness) {
+                Some((n, replacement)) => {
+                    edits.push((i..i + n, replacement));
+                    i += n;
+                }
+                None => i += 1,
+            }
+        }
+        // Edit from the last line up, so earlier line indices stay valid
+        let mut result = program.clone();
+        for (range, lines) in edits.into_iter().rev() {
+            result.lines.splice(range, lines);
+        }
+        let changed = result != *program;
+        *program = result;
+        Ok(changed)
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashMap};
+
+use petgraph::graph::{NodeIndex, UnGraph};
+
+use crate::{
+    analysis::{Aliases, Effects, Liveness, Register, RegisterSet},
+    cfg::Cfg,
+    instruction::{signature, ArgKind},
+    profile::HardwareProfile,
+    program::{Instruction, Line, Program},
+};
+
+use super::Pass;
+
+/// Maps virtual registers (names written as registers without an `alias`) onto the
+/// physical registers, colouring the graph of registers live at the same time.
+///
+/// When more registers are live at once than the profile has, some virtual registers are
+/// spilled to the top of the IC's stack memory, loaded with `get` before every use and
+/// stored with `put` after every write, through scratch registers the program leaves free.
+pub struct RegisterAllocation;
+
+/// Registers live at the same time on some line
+struct Interference {
+    graph: UnGraph<Register, ()>,
+    nodes: HashMap<Register, NodeIndex>,
+}
+
+impl Interference {
+    fn build(occupied: &[RegisterSet]) -> Self {
+        let mut interference = Self {
+            graph: UnGraph::new_undirected(),
+            nodes: HashMap::new(),
+        };
+        for regs in occupied.iter() {
+            let nodes: Vec<NodeIndex> = regs.iter().map(|r| interference.node(*r)).collect();
+            for (i, a) in nodes.iter().enumerate() {
+                for b in nodes[i + 1..].iter() {
+                    interference.graph.update_edge(*a, *b, ());
+                }
+            }
+        }
+        interference
+    }
+
+    fn node(&mut self, r: Register) -> NodeIndex {
+        let graph = &mut self.graph;
+        *self.nodes.entry(r).or_insert_with(|| graph.add_node(r))
+    }
+
+    fn neighbors(&self, r: Register) -> impl Iterator<Item = Register> + '_ {
+        self.graph
+            .neighbors(self.nodes[&r])
+            .map(move |n| self.graph[n])
+    }
+
+    /// Colour the virtual registers with `palette`, most constrained first. Virtual
+    /// registers left without a colour are spilled.
+    fn colour(&self, virtuals: &[Register], palette: &[Register]) -> HashMap<Register, Register> {
+        let mut order = virtuals.to_vec();
+        order.sort_by_key(|r| std::cmp::Reverse(self.neighbors(*r).count()));
+        let mut colours: HashMap<Register, Register> = HashMap::new();
+        for v in order {
+            let taken: RegisterSet = self
+                .neighbors(v)
+                .filter_map(|n| {
+                    if n.is_virtual() {
+                        colours.get(&n).cloned()
+                    } else {
+                        Some(n)
+                    }
+                })
+                .collect();
+            if let Some(r) = palette.iter().find(|r| !taken.contains(r)) {
+                colours.insert(v, *r);
+            }
+        }
+        colours
+    }
+}
+
+impl Pass for RegisterAllocation {
+    fn name(&self) -> &'static str {
+        "registers"
+    }
+
+    fn run(&self, program: &mut Program, profile: &HardwareProfile) -> Result<bool, String> {
+        let aliases = Aliases::of(program);
+        let virtuals: Vec<Register> = (0..aliases.virtuals().count())
+            .map(Register::Virtual)
+            .collect();
+        if virtuals.is_empty() {
+            return Ok(false);
+        }
+
+        let liveness = Liveness::compute(program, &Cfg::build(program));
+        let effects: Vec<Option<Effects>> = program
+            .lines
+            .iter()
+            .map(|l| l.instruction().map(|i| Effects::of(i, &aliases)))
+            .collect();
+        let occupied: Vec<RegisterSet> = (0..program.len())
+            .map(|i| {
+                let defs = effects[i].iter().flat_map(|e| e.may_defs.iter());
+                let live = liveness.live_in[i].union(&liveness.live_out[i]);
+                live.chain(defs).cloned().collect()
+            })
+            .collect();
+        let interference = Interference::build(&occupied);
+        // Virtual registers of every instruction
+        let accessed: Vec<RegisterSet> = effects
+            .iter()
+            .map(|e| {
+                e.iter()
+                    .flat_map(|e| e.uses.iter().chain(e.may_defs.iter()))
+                    .filter(|r| r.is_virtual())
+                    .cloned()
+                    .collect()
+            })
+            .collect();
+
+        // Physical registers the program does not use, the last ones serve as scratch
+        let physical: Vec<Register> = (0..profile.registers).map(Register::R).collect();
+        let unused: Vec<Register> = physical
+            .iter()
+            .filter(|r| !interference.nodes.contains_key(r))
+            .cloned()
+            .collect();
+        let mut nscratch = 0;
+        let (colours, scratch) = loop {
+            if nscratch > unused.len() {
+                return Err(format!(
+                    "Not enough free registers to spill to, {} needed",
+                    nscratch
+                ));
+            }
+            let scratch = &unused[unused.len() - nscratch..];
+            let palette: Vec<Register> = physical
+                .iter()
+                .filter(|r| !scratch.contains(r))
+                .cloned()
+                .collect();
+            let colours = interference.colour(&virtuals, &palette);
+            let needed = accessed
+                .iter()
+                .map(|regs| regs.iter().filter(|r| !colours.contains_key(r)).count())
+                .max()
+                .unwrap_or(0);
+            if needed <= nscratch {
+                break (colours, scratch.to_vec());
+            }
+            nscratch = needed;
+        };
+
+        // Spilled registers, by address in the stack memory
+        let spilled: BTreeMap<Register, usize> = virtuals
+            .iter()
+            .filter(|v| !colours.contains_key(v))
+            .enumerate()
+            .map(|(i, v)| (*v, profile.stack_size - 1 - i))
+            .collect();
+        for (i, instr) in program.instructions().filter(|(_, i)| i.op == "alias") {
+            if let Some(v) = instr.args.last() {
+                if aliases
+                    .virtual_register(v)
+                    .is_some_and(|r| spilled.contains_key(&r))
+                {
+                    return Err(format!("Line {}: cannot spill '{}', it is aliased", i, v));
+                }
+            }
+        }
+        let mut lines = Vec::with_capacity(program.len());
+        for (i, line) in program.lines.iter().enumerate() {
+            let instr = match line {
+                Line::Instruction(instr) => instr,
+                _ => {
+                    lines.push(line.clone());
+                    continue;
+                }
+            };
+            let e = effects[i].as_ref().unwrap();
+            // Scratch register of every spilled register the instruction accesses
+            let temps: BTreeMap<Register, Register> = accessed[i]
+                .iter()
+                .filter(|r| spilled.contains_key(r))
+                .cloned()
+                .zip(scratch.iter().cloned())
+                .collect();
+            let mut instr = instr.clone();
+            let kinds = signature(&instr.op).unwrap_or(&[]);
+            for (a, kind) in instr.args.iter_mut().zip(kinds) {
+                if matches!(kind, ArgKind::Device | ArgKind::Token) {
+                    continue;
+                }
+                let v = match aliases.virtual_register(a) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                if let Some(r) = colours.get(&v).or_else(|| temps.get(&v)) {
+                    *a = aliases.name(*r);
+                }
+            }
+            for (v, temp) in temps.iter().filter(|(v, _)| e.uses.contains(v)) {
+                let address = spilled[v].to_string();
+                let get = Instruction::new("get", &[&aliases.name(*temp), "db", &address]);
+                lines.push(Line::Instruction(get));
+            }
+            lines.push(Line::Instruction(instr));
+            for (v, temp) in temps.iter().filter(|(v, _)| e.may_defs.contains(v)) {
+                let address = spilled[v].to_string();
+                let put = Instruction::new("put", &["db", &address, &aliases.name(*temp)]);
+                lines.push(Line::Instruction(put));
+            }
+        }
+        program.lines = lines;
+        Ok(true)
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{
+    analysis::Aliases,
+    branch::Comparison,
+    instruction::signature,
+    profile::HardwareProfile,
+    program::{literal_value, Instruction, Line, Program},
+};
+
+use super::Pass;
+
+/// Turns if/else diamonds assigning the same register into a `select`:
+///
+/// ```text
+/// bgt a b L          sgt r0 a b
+/// move r0 x          select r0 r0 y x
+/// j E
+/// L:
+/// move r0 y
+/// E:
+/// ```
+///
+/// Branches testing a register against zero (`bnez c L`, `beqz c L`) need no set
+/// instruction, they become a single `select r0 c y x`. Diamonds assigning 1 and 0 need no
+/// `select`, nor do diamonds assigning 0 and 1 on a condition with an exact inverse
+/// (`seq`/`sne`, not `sgt`/`sle`).
+pub struct BranchToSelect;
+
+/// Diamond from line `start` to its end label, on line `end`
+struct Diamond<'a> {
+    branch: &'a Instruction,
+    /// Register assigned, with its value when the branch is not taken, then when it is
+    register: &'a str,
+    not_taken: &'a str,
+    taken: &'a str,
+    end: usize,
+}
+
+impl<'a> Diamond<'a> {
+    fn find(program: &'a Program, start: usize) -> Option<Self> {
+        let (branch, else_move, jump, label, then_move) =
+            match program.lines.get(start..start + 5)? {
+                [b, e, j, Line::Label(l), t] => (
+                    b.instruction()?,
+                    e.instruction()?,
+                    j.instruction()?,
+                    l,
+                    t.instruction()?,
+                ),
+                _ => return None,
+            };
+        let b = branch.branch()?;
+        if !b.is_conditional() || b.link || b.relative || branch.target() != Some(label) {
+            return None;
+        }
+        // Only entered through the branch
+        if program.references(label) != [start] {
+            return None;
+        }
+        let (register, not_taken, taken) = match (
+            else_move.op.as_str(),
+            else_move.args.as_slice(),
+            then_move.op.as_str(),
+            then_move.args.as_slice(),
+        ) {
+            ("move", [r, x], "move", [s, y]) if r == s => (r, x, y),
+            _ => return None,
+        };
+        let end = match (jump.op.as_str(), jump.args.as_slice()) {
+            ("j", [e]) => *program.labels().get(e)?,
+            _ => return None,
+        };
+        let joins = end >= start + 5
+            && program.lines[start + 5..end]
+                .iter()
+                .all(|l| matches!(l, Line::Label(_)));
+        if !joins {
+            return None;
+        }
+        Some(Self {
+            branch,
+            register,
+            not_taken,
+            taken,
+            end,
+        })
+    }
+
+    /// Instructions computing the register without branching
+    fn select(&self, aliases: &Aliases) -> Option<Vec<Instruction>> {
+        let condition = self.branch.branch()?.condition?;
+        let operands = &self.branch.args[..self.branch.args.len() - 1];
+        let picked = match (condition.comparison, condition.zero, operands) {
+            (Comparison::Ne, true, [c]) => Some((c, self.taken, self.not_taken)),
+            (Comparison::Eq, true, [c]) => Some((c, self.not_taken, self.taken)),
+            _ => None,
+        };
+        if let Some((c, b, d)) = picked {
+            return Some(vec![Instruction::new("select", &[self.register, c, b, d])]);
+        }
+        // The register holds the condition until the select, so the values must not read it
+        let r = aliases.registers(self.register).filter(|r| !r.is_empty())?;
+        let reads_r = |token: &str| match aliases.registers(token) {
+            Some(regs) => !regs.is_disjoint(&r),
+            None => true,
+        };
+        if reads_r(self.taken) || reads_r(self.not_taken) {
+            return None;
+        }
+        let mut set_args = vec![self.register];
+        set_args.extend(operands.iter().map(String::as_str));
+        // Assigning 1 or 0 is what the set instruction does by itself
+        let (condition, select) = match (literal_value(self.taken), literal_value(self.not_taken)) {
+            (Some(t), Some(n)) if t == 1.0 && n == 0.0 => (condition, false),
+            (Some(t), Some(n)) if t == 0.0 && n == 1.0 => match condition.inverse() {
+                Some(inverse) => (inverse, false),
+                // `sle` is not the opposite of `sgt` for NaN operands
+                None => (condition, true),
+            },
+            _ => (condition, true),
+        };
+        let set_op = condition.set_op();
+        signature(&set_op)?;
+        let mut instrs = vec![Instruction::new(&set_op, &set_args)];
+        if select {
+            instrs.push(Instruction::new(
+                "select",
+                &[self.register, self.register, self.taken, self.not_taken],
+            ));
+        }
+        Some(instrs)
+    }
+}
+
+impl Pass for BranchToSelect {
+    fn name(&self) -> &'static str {
+        "select"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        let aliases = Aliases::of(program);
+        let mut edits = Vec::new();
+        let mut i = 0;
+        while i < program.len() {
+            let diamond = Diamond::find(program, i);
+            match diamond.as_ref().and_then(|d| d.select(&aliases)) {
+                Some(select) => {
+                    let end = diamond.unwrap().end;
+                    edits.push((i..i + 5, select));
+                    i = end;
+                }
+                None => i += 1,
+            }
+        }
+        let changed = !edits.is_empty();
+        // Edit from the last line up, so earlier line indices stay valid
+        for (range, instrs) in edits.into_iter().rev() {
+            let lines = instrs.into_iter().map(Line::Instruction);
+            program.lines.splice(range, lines);
+        }
+        Ok(changed)
+    }
+}
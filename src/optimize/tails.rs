@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+    profile::HardwareProfile,
+    program::{fresh_name, Line, Program},
+};
+
+use super::Pass;
+
+/// Merges identical instructions ending the code paths into the same label, keeping one
+/// copy that the other paths jump into:
+///
+/// ```text
+/// bgtz r0 L          bgtz r0 L
+/// move r1 2          move r1 2
+/// s d0 On r1         j M
+/// j E                L:
+/// L:                 move r1 1
+/// move r1 1          M:
+/// s d0 On r1         s d0 On r1
+/// E:                 E:
+/// ```
+///
+/// The copy kept is the one running into the label when there is one, so paths ending
+/// with a `j` only have the jump retargeted and every merge saves lines. Paths jumping to
+/// the label may also share a copy among themselves. Merging costs an
+/// extra jump on the path running into the label when it has to jump instead, this pass
+/// is thus only run when optimizing for size.
+pub struct MergeTails;
+
+/// Instruction lines running into line `end` (excluded), from the last label or
+/// unconditional jump on
+fn tail(program: &Program, end: usize) -> Vec<usize> {
+    let mut lines = Vec::new();
+    for i in (0..end).rev() {
+        match &program.lines[i] {
+            Line::Empty => {}
+            Line::Label(_) => break,
+            Line::Instruction(instr) => match instr.branch() {
+                Some(b) if b.relative || (!b.is_conditional() && !b.link) => break,
+                _ => lines.push(i),
+            },
+        }
+    }
+    lines.reverse();
+    lines
+}
+
+/// Number of identical instructions ending the tails `a` and `b`
+fn common_suffix(program: &Program, a: &[usize], b: &[usize]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(i, j)| program.lines[**i] == program.lines[**j])
+        .count()
+}
+
+impl Pass for MergeTails {
+    fn name(&self) -> &'static str {
+        "tails"
+    }
+
+    fn run(&self, program: &mut Program, _profile: &HardwareProfile) -> Result<bool, String> {
+        // Labels to add in front of lines, lines to remove and jumps to retarget
+        let mut added: BTreeMap<usize, String> = BTreeMap::new();
+        let mut removed: HashSet<usize> = HashSet::new();
+        let mut retargeted: HashMap<usize, String> = HashMap::new();
+        let mut taken: HashSet<String> = HashSet::new();
+
+        let mut labels: Vec<(String, usize)> = program.labels().into_iter().collect();
+        labels.sort_by_key(|(_, at)| *at);
+        for (label, at) in labels {
+            let jumps: Vec<usize> = program
+                .references(&label)
+                .into_iter()
+                .filter(|i| {
+                    let instr = program.lines[*i].instruction();
+                    instr.is_some_and(|instr| instr.op == "j" && instr.args == [label.as_str()])
+                })
+                .collect();
+            let first = (0..at)
+                .rev()
+                .find(|i| program.lines[*i].instruction().is_some());
+            let falls_in = match first {
+                Some(i) => tail(program, i + 1).last() == Some(&i),
+                None => false,
+            };
+            // Keep the copy running into the label, or else the first one jumping to it
+            let (keep, others) = match (falls_in, jumps.split_first()) {
+                (true, _) => (tail(program, first.unwrap() + 1), &jumps[..]),
+                (false, Some((j, others))) => (tail(program, *j), others),
+                (false, None) => continue,
+            };
+            // Copies other paths can jump into, with how many of their last lines are gone
+            let mut kept: Vec<(Vec<usize>, usize)> = vec![(keep, 0)];
+            for j in others {
+                let other = tail(program, *j);
+                let best = kept
+                    .iter()
+                    .map(|(k, gone)| (k, common_suffix(program, k, &other), *gone))
+                    .filter(|(_, n, gone)| n > gone)
+                    .max_by_key(|(_, n, _)| *n)
+                    .map(|(k, n, _)| (k[k.len() - n], n));
+                let (shared, n) = match best {
+                    Some(best) => best,
+                    None => {
+                        kept.push((other, 0));
+                        continue;
+                    }
+                };
+                let name = added.entry(shared).or_insert_with(|| {
+                    let name = fresh_name(&format!("{}_tail", label), |n| {
+                        program.is_name_taken(n) || taken.contains(n)
+                    });
+                    taken.insert(name.clone());
+                    name
+                });
+                removed.extend(other[other.len() - n..].iter().copied());
+                retargeted.insert(*j, name.clone());
+                kept.push((other, n));
+            }
+        }
+        if retargeted.is_empty() {
+            return Ok(false);
+        }
+
+        let mut lines = Vec::with_capacity(program.len() + added.len());
+        for (i, line) in program.lines.iter().enumerate() {
+            if let Some(name) = added.get(&i) {
+                lines.push(Line::Label(name.clone()));
+            }
+            if removed.contains(&i) {
+                continue;
+            }
+            match retargeted.get(&i) {
+                Some(name) => {
+                    let mut line = line.clone();
+                    line.instruction_mut().unwrap().args[0] = name.clone();
+                    lines.push(line);
+                }
+                None => lines.push(line.clone()),
+            }
+        }
+        program.lines = lines;
+        Ok(true)
+    }
+}
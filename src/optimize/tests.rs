@@ -0,0 +1,291 @@
+use crate::{program::Program, scenario::Scenario};
+
+use super::*;
+
+/// Run the scenario `examples/optimize/<name>.toml` with its scripts as written, then
+/// rewritten by `optimize`, checking the rewrite changed a script and that both runs pass
+/// with the same values. Scripts that cannot run as written (`runs = false`) only need to
+/// pass once rewritten.
+fn check<F>(name: &str, runs: bool, optimize: F)
+where
+    F: Fn(&Program, &HardwareProfile) -> Result<Program, String>,
+{
+    let path = format!("examples/optimize/{}.toml", name);
+    let mut scenario = Scenario::from_file(&path).unwrap();
+    let before = scenario.run().unwrap();
+    assert!(!runs || before.passed(), "{} as written:\n{}", path, before);
+
+    let profile = scenario.ic.profile().unwrap();
+    let mut changed = false;
+    let paths: Vec<String> = scenario
+        .script_paths()
+        .into_iter()
+        .map(str::to_owned)
+        .collect();
+    for script in paths {
+        let program = Program::parse(&scenario.script_lines(&script).unwrap());
+        let optimized = optimize(&program, &profile).unwrap();
+        changed |= optimized != normalize(&program).unwrap();
+        scenario.set_script(&script, optimized.to_lines());
+    }
+    assert!(changed, "{}: nothing to optimize", path);
+
+    let after = scenario.run().unwrap();
+    assert!(after.passed(), "{} optimized:\n{}", path, after);
+    let same = |a: &Result<f64, String>, b: &Result<f64, String>| match (a, b) {
+        (Ok(a), Ok(b)) => a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan()),
+        (a, b) => a == b,
+    };
+    for (b, a) in before
+        .results
+        .iter()
+        .zip(after.results.iter())
+        .filter(|_| runs)
+    {
+        assert!(
+            same(&b.actual, &a.actual),
+            "{}: {} was {:?} as written, {:?} optimized",
+            path,
+            b.description,
+            b.actual,
+            a.actual
+        );
+    }
+}
+
+/// Check `pass` alone on the scenario `name`, then every pass
+fn check_pass(name: &str, pass: impl Pass) {
+    check_rewrite(name, true, pass);
+}
+
+fn check_rewrite(name: &str, runs: bool, pass: impl Pass) {
+    check(name, runs, |program, profile| {
+        let mut program = normalize(program)?;
+        pass.run(&mut program, profile)?;
+        Ok(program)
+    });
+    check(name, runs, |program, profile| {
+        Optimizer::new(profile.clone()).optimize(program)
+    });
+}
+
+#[test]
+fn inline() {
+    check_pass("inline", InlineSubroutines);
+}
+
+#[test]
+fn inline_leaves_subroutines_reading_ra() {
+    let profile = HardwareProfile::by_name("ic10").unwrap();
+    let callee_reads = [
+        "jal f",
+        "s d0 On r0",
+        "j end",
+        "f:",
+        "move r0 ra",
+        "j ra",
+        "end:",
+    ];
+    let caller_reads = [
+        "jal f",
+        "s d0 On ra",
+        "j end",
+        "f:",
+        "move r0 1",
+        "j ra",
+        "end:",
+    ];
+    for lines in [callee_reads, caller_reads] {
+        let mut program = Program::parse(&lines);
+        assert_eq!(InlineSubroutines.run(&mut program, &profile), Ok(false));
+        // Labels are then resolved
+        let optimized = Optimizer::new(profile.clone())
+            .optimize(&Program::parse(&lines))
+            .unwrap();
+        assert!(optimized.labels().is_empty(), "{:?}", optimized);
+    }
+}
+
+#[test]
+fn dead_code() {
+    check_pass("dead_code", DeadCode);
+}
+
+#[test]
+fn compare_branch() {
+    check_pass("compare_branch", FuseCompareBranch);
+}
+
+#[test]
+fn jumps() {
+    check_pass("jumps", SimplifyJumps);
+}
+
+#[test]
+fn layout() {
+    check_pass("layout", BlockLayout);
+}
+
+#[test]
+fn select() {
+    check_pass("select", BranchToSelect);
+}
+
+#[test]
+fn peephole() {
+    check_pass(
+        "peephole",
+        Peephole::from_file("rules/peephole.rules").unwrap(),
+    );
+}
+
+#[test]
+fn peephole_keeps_registers_read() {
+    // No `when $t dead`, the engine checks it anyway
+    let rules = "seq $t $a $b; beqz $t $L => bne $a $b $L";
+    check_pass("peephole", Peephole::parse(rules).unwrap());
+}
+
+#[test]
+fn peephole_rejects_invalid_rules() {
+    let errors = [
+        (
+            "add $r $a => move $r $a",
+            "Invalid rule 'add $r $a => move $r $a': 'add' takes 3 arguments, not 2",
+        ),
+        (
+            "move $r $a => move $r",
+            "Invalid rule 'move $r $a => move $r': 'move' takes 2 arguments, not 1",
+        ),
+        (
+            "beqz $a $L; move $r $a => bnez $a $L",
+            "Invalid rule 'beqz $a $L; move $r $a => bnez $a $L': \
+             'beqz $a $L' branches before the last instruction",
+        ),
+        (
+            "seq $t $a $b; beqz $t $L => bne $a $b $L; move $t 0",
+            "Invalid rule 'seq $t $a $b; beqz $t $L => bne $a $b $L; move $t 0': \
+             'bne $a $b $L' branches before the last instruction",
+        ),
+        (
+            "frob $a => move $a 0",
+            "Invalid rule 'frob $a => move $a 0': Unknown instruction 'frob'",
+        ),
+    ];
+    for (rule, error) in errors {
+        assert_eq!(Peephole::parse(rule).unwrap_err(), error);
+    }
+}
+
+#[test]
+fn constants() {
+    check_pass("constants", Constants);
+}
+
+#[test]
+fn constants_keep_lines_short() {
+    let program = Program::parse(&["move r0 0.33333334", "s d0 Setting r0"]);
+    let mut optimized = program.clone();
+    let profile = HardwareProfile::by_name("ic10").unwrap();
+    Constants.run(&mut optimized, &profile).unwrap();
+    assert_eq!(optimized.lines[1], program.lines[1]);
+
+    // Nor folds results longer than the instruction computing them
+    let program = Program::parse(&["div r0 1 3", "mul r1 r0 3", "s d0 Setting r1"]);
+    let mut optimized = program.clone();
+    Constants.run(&mut optimized, &profile).unwrap();
+    assert_eq!(optimized.lines[0], program.lines[0]);
+    assert_eq!(optimized.lines[1], Line::parse("move r1 1"));
+}
+
+#[test]
+fn algebra() {
+    check_pass("algebra", Simplify);
+}
+
+#[test]
+fn cse() {
+    check_pass("cse", CommonSubexpressions::default());
+}
+
+#[test]
+fn cse_reloads_device_reads() {
+    let lines = [
+        "sdse r0 d1",
+        "get r1 d0 0",
+        "sdse r2 d1",
+        "get r3 d0 0",
+        "add r4 r0 r2",
+        "add r5 r1 r3",
+        "s db Setting r4",
+        "s db On r5",
+    ];
+    let mut program = Program::parse(&lines);
+    let profile = HardwareProfile::by_name("ic10").unwrap();
+    let changed = CommonSubexpressions::default().run(&mut program, &profile);
+    assert_eq!(changed, Ok(false));
+}
+
+#[test]
+fn copies() {
+    check_pass("copies", CopyPropagation);
+}
+
+#[test]
+fn licm() {
+    check_pass("licm", HoistInvariants);
+}
+
+#[test]
+fn licm_keeps_device_reads() {
+    let lines = [
+        "start:",
+        "yield",
+        "sdse r1 d1",
+        "mul r4 r1 3",
+        "s db Setting r4",
+        "j start",
+    ];
+    let mut program = Program::parse(&lines);
+    let profile = HardwareProfile::by_name("ic10").unwrap();
+    assert_eq!(HoistInvariants.run(&mut program, &profile), Ok(false));
+}
+
+#[test]
+fn registers() {
+    check_rewrite("registers", false, RegisterAllocation);
+}
+
+#[test]
+fn bindings() {
+    check_pass("bindings", EliminateBindings);
+}
+
+#[test]
+fn tails() {
+    // Only run when optimizing for size
+    check("tails", true, |program, profile| {
+        let mut program = normalize(program)?;
+        MergeTails.run(&mut program, profile)?;
+        Ok(program)
+    });
+    check("tails", true, |program, profile| {
+        Optimizer::new(profile.clone())
+            .with_pass(Box::new(MergeTails))
+            .optimize(program)
+    });
+}
+
+#[test]
+fn labels() {
+    check_pass("labels", ResolveLabels);
+}
+
+#[test]
+fn labels_used_as_values_stay() {
+    let lines = ["loop:", "move r0 loop", "add r0 r0 2", "yield", "j loop"];
+    let mut program = Program::parse(&lines);
+    let profile = HardwareProfile::by_name("ic10").unwrap();
+    assert_eq!(ResolveLabels.run(&mut program, &profile), Ok(false));
+    assert_eq!(program, Program::parse(&lines));
+}
@@ -0,0 +1,171 @@
+use crate::size::SizeReport;
+
+/// IC hardware limits, enforced when loading a program and while running it.
+///
+/// `None` limits are unbounded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HardwareProfile {
+    pub name: String,
+    pub pins: usize,
+    pub registers: usize,
+    pub stack_size: usize,
+    pub max_lines: Option<usize>,
+    /// Maximum characters per line
+    pub max_line_length: Option<usize>,
+    /// Maximum size of the whole program in bytes, newlines included
+    pub max_bytes: Option<usize>,
+    pub instr_per_tick: usize,
+}
+
+impl HardwareProfile {
+    /// The in-game IC10 chip
+    pub fn ic10() -> Self {
+        Self {
+            name: "ic10".to_owned(),
+            pins: 6,
+            registers: 16,
+            stack_size: 512,
+            max_lines: Some(128),
+            max_line_length: Some(90),
+            max_bytes: Some(4096),
+            instr_per_tick: 128,
+        }
+    }
+
+    /// IC10 without program size limits, for experimentation
+    pub fn unlimited() -> Self {
+        Self {
+            name: "unlimited".to_owned(),
+            max_lines: None,
+            max_line_length: None,
+            max_bytes: None,
+            ..Self::ic10()
+        }
+    }
+
+    /// Profile without program size limits and the given hardware
+    pub fn custom(pins: usize, registers: usize, stack_size: usize, instr_per_tick: usize) -> Self {
+        Self {
+            name: "custom".to_owned(),
+            pins,
+            registers,
+            stack_size,
+            instr_per_tick,
+            ..Self::unlimited()
+        }
+    }
+
+    pub fn by_name(name: &str) -> Result<Self, String> {
+        match name {
+            "ic10" => Ok(Self::ic10()),
+            "unlimited" => Ok(Self::unlimited()),
+            _ => Err(format!("Unknown hardware profile '{}'", name)),
+        }
+    }
+
+    /// Check a program against the size limits, reporting every one exceeded
+    pub fn check_program(&self, lines: &[String]) -> Result<(), String> {
+        let violations = SizeReport::new(self, lines).violations();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Program does not fit '{}': {}",
+                self.name,
+                violations.join("; ")
+            ))
+        }
+    }
+}
+
+impl Default for HardwareProfile {
+    fn default() -> Self {
+        Self::ic10()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ic::ICState, instruction::StationeersInstructionSet, try_run_tick};
+
+    fn lines(n: usize, length: usize) -> Vec<String> {
+        vec!["#".repeat(length); n]
+    }
+
+    #[test]
+    fn line_limit() {
+        let ic10 = HardwareProfile::ic10();
+        assert_eq!(ic10.check_program(&lines(128, 1)), Ok(()));
+        assert_eq!(
+            ic10.check_program(&lines(129, 1)),
+            Err(
+                "Program does not fit 'ic10': 1 lines over the 128 line limit \
+                 (lines 128-128 do not fit)"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn line_length_limit() {
+        let ic10 = HardwareProfile::ic10();
+        assert_eq!(ic10.check_program(&lines(2, 90)), Ok(()));
+        let mut program = lines(2, 90);
+        program[1].push('#');
+        assert_eq!(
+            ic10.check_program(&program),
+            Err(
+                "Program does not fit 'ic10': line 1 has 91 characters, over the 90 limit"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn byte_limit() {
+        // 45 lines of 90 characters and their newlines take 4095 bytes
+        let ic10 = HardwareProfile::ic10();
+        let mut program = lines(45, 90);
+        program.push("#".to_owned());
+        assert_eq!(ic10.check_program(&program), Ok(()));
+        program[45].push('#');
+        assert_eq!(
+            ic10.check_program(&program),
+            Err("Program does not fit 'ic10': 1 bytes over the 4096 byte limit".to_owned())
+        );
+        assert_eq!(HardwareProfile::unlimited().check_program(&program), Ok(()));
+    }
+
+    fn run(profile: HardwareProfile, lines: &[&str]) -> Result<(), String> {
+        let mut ic = ICState::from_profile(profile);
+        let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        try_run_tick(&mut ic, &lines, &StationeersInstructionSet::new())
+    }
+
+    #[test]
+    fn register_limit() {
+        let profile = HardwareProfile::custom(2, 4, 8, 128);
+        assert_eq!(
+            run(profile.clone(), &["move r3 1", "move ra 1", "move sp 1"]),
+            Ok(())
+        );
+        assert!(run(profile, &["move r4 1"]).is_err());
+    }
+
+    #[test]
+    fn stack_limit() {
+        let profile = HardwareProfile::custom(2, 4, 2, 128);
+        assert_eq!(run(profile.clone(), &["push 1", "push 2"]), Ok(()));
+        assert!(run(profile.clone(), &["push 1", "push 2", "push 3"]).is_err());
+        assert_eq!(run(profile.clone(), &["put db 1 1"]), Ok(()));
+        assert!(run(profile, &["put db 2 1"]).is_err());
+    }
+
+    #[test]
+    fn pin_limit() {
+        let profile = HardwareProfile::custom(2, 4, 2, 128);
+        assert_eq!(run(profile.clone(), &["sdse r0 d1"]), Ok(()));
+        assert!(run(profile, &["sdse r0 d2"]).is_err());
+    }
+}
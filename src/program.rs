@@ -0,0 +1,251 @@
+use std::{collections::HashMap, fmt};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{branch::Branch, hash::hash};
+
+lazy_static! {
+    static ref PATTERN_LABEL: Regex = Regex::new(r"^(\w+):").unwrap();
+    static ref PATTERN_REGISTER: Regex = Regex::new(r"^(r\d+|ra|sp)$").unwrap();
+    static ref PATTERN_DEVICE: Regex = Regex::new(r"^(d\d+|db)$").unwrap();
+}
+
+/// A single parsed instruction
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub op: String,
+    pub args: Vec<String>,
+}
+
+impl Instruction {
+    pub fn new(op: &str, args: &[&str]) -> Self {
+        Self {
+            op: op.to_owned(),
+            args: args.iter().map(|&a| a.to_owned()).collect(),
+        }
+    }
+
+    /// Jump or branch made by this instruction, if any
+    pub fn branch(&self) -> Option<Branch> {
+        Branch::parse(&self.op)
+    }
+
+    /// Target operand of a jump or branch (always its last argument)
+    pub fn target(&self) -> Option<&str> {
+        self.branch()
+            .and_then(|_| self.args.last())
+            .map(String::as_str)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.op)?;
+        for a in self.args.iter() {
+            write!(f, " {}", a)?;
+        }
+        Ok(())
+    }
+}
+
+/// A line of a program
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+    /// Blank or comment-only line
+    Empty,
+    Label(String),
+    Instruction(Instruction),
+}
+
+impl Line {
+    pub fn parse(line: &str) -> Self {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if let Some(label) = PATTERN_LABEL.captures(line).and_then(|m| m.get(1)) {
+            Line::Label(label.as_str().to_owned())
+        } else {
+            let mut tokens = tokens(line).into_iter();
+            match tokens.next() {
+                Some(op) => Line::Instruction(Instruction {
+                    op: op.to_owned(),
+                    args: tokens.map(str::to_owned).collect(),
+                }),
+                None => Line::Empty,
+            }
+        }
+    }
+
+    pub fn instruction(&self) -> Option<&Instruction> {
+        match self {
+            Line::Instruction(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn instruction_mut(&mut self) -> Option<&mut Instruction> {
+        match self {
+            Line::Instruction(i) => Some(i),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Empty => Ok(()),
+            Line::Label(l) => write!(f, "{}:", l),
+            Line::Instruction(i) => write!(f, "{}", i),
+        }
+    }
+}
+
+/// Tokens of an instruction line, split on whitespace outside of double quotes so
+/// `HASH("Wall Heater")` stays one token
+pub fn tokens(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        if c.is_whitespace() && !quoted {
+            if let Some(s) = start.take() {
+                tokens.push(&line[s..i]);
+            }
+            continue;
+        }
+        start.get_or_insert(i);
+        if c == '"' {
+            quoted = !quoted;
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(&line[s..]);
+    }
+    tokens
+}
+
+/// Whether `token` names one of the IC's registers (`r0`..., `ra`, `sp`)
+pub fn is_register(token: &str) -> bool {
+    PATTERN_REGISTER.is_match(token)
+}
+
+/// Whether `token` names one of the IC's pins or its housing (`d0`..., `db`)
+pub fn is_device(token: &str) -> bool {
+    PATTERN_DEVICE.is_match(token)
+}
+
+/// Whether `token` is a number literal (or a `HASH("...")`)
+pub fn is_literal(token: &str) -> bool {
+    token.parse::<f64>().is_ok() || token.starts_with("HASH(\"")
+}
+
+/// Value of a number literal (or a `HASH("...")`)
+pub fn literal_value(token: &str) -> Option<f64> {
+    token.parse().ok().or_else(|| {
+        token
+            .strip_prefix("HASH(\"")
+            .and_then(|t| t.strip_suffix("\")"))
+            .map(|s| hash(s) as f64)
+    })
+}
+
+/// `base`, or `base` with the first numbered suffix for which `taken` is false
+pub fn fresh_name<F: Fn(&str) -> bool>(base: &str, taken: F) -> String {
+    if !taken(base) {
+        return base.to_owned();
+    }
+    (1..)
+        .map(|i| format!("{}_{}", base, i))
+        .find(|name| !taken(name))
+        .unwrap()
+}
+
+/// An IC10 program parsed line by line, line indices match the source
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub lines: Vec<Line>,
+}
+
+impl Program {
+    pub fn parse<S: AsRef<str>>(lines: &[S]) -> Self {
+        Self {
+            lines: lines.iter().map(|l| Line::parse(l.as_ref())).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Instructions with their line indices
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, &Instruction)> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, l)| l.instruction().map(|instr| (i, instr)))
+    }
+
+    /// Line index of every label
+    pub fn labels(&self) -> HashMap<String, usize> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter_map(|(i, l)| match l {
+                Line::Label(name) => Some((name.clone(), i)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Value of every name defined (with a literal) by exactly one `define`
+    pub fn defines(&self) -> HashMap<String, f64> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut values = HashMap::new();
+        for (_, instr) in self.instructions().filter(|(_, i)| i.op == "define") {
+            if let [name, value] = instr.args.as_slice() {
+                *counts.entry(name).or_insert(0) += 1;
+                if let Ok(v) = value.parse::<f64>() {
+                    values.insert(name.clone(), v);
+                }
+            }
+        }
+        values.retain(|name, _| counts.get(name.as_str()) == Some(&1));
+        values
+    }
+
+    /// Lines of the instructions using `token` as an argument
+    pub fn references(&self, token: &str) -> Vec<usize> {
+        self.instructions()
+            .filter(|(_, instr)| instr.args.iter().any(|a| a == token))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Whether a label, alias or define of the program uses `name`
+    pub fn is_name_taken(&self, name: &str) -> bool {
+        self.lines.iter().any(|l| match l {
+            Line::Label(l) => l == name,
+            Line::Instruction(i) => i.args.iter().any(|a| a == name),
+            Line::Empty => false,
+        })
+    }
+
+    /// `base`, or `base` with a numbered suffix if the program already uses it
+    pub fn fresh_name(&self, base: &str) -> String {
+        fresh_name(base, |name| self.is_name_taken(name))
+    }
+
+    pub fn to_lines(&self) -> Vec<String> {
+        self.lines.iter().map(Line::to_string).collect()
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_lines().join("\n"))
+    }
+}
@@ -0,0 +1,708 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    alias::Alias,
+    behavior::{ActiveVent, Battery, DaylightSensor, Heater},
+    device::DeviceType,
+    ic::ICState,
+    instruction::{InstructionSet, StationeersInstructionSet},
+    network::ReferenceId,
+    profile::HardwareProfile,
+    read_lines,
+    timeline::Timeline,
+    world::World,
+};
+
+/// Network devices are connected to unless they name another one
+const DEFAULT_NETWORK: &str = "default";
+
+/// Name of the IC described by the top-level `script`
+const DEFAULT_IC: &str = "main";
+
+/// Declarative simulation setup with expected outcomes, loaded from TOML.
+///
+/// A single IC is described by the top-level `script` and `[ic]` (it is named `main`),
+/// several ICs sharing devices by `[[ics]]` entries. Devices and IC housings are connected
+/// to the network named by `network`, or to the `default` network. An IC's housing is a
+/// device labelled with the IC's name, e.g. `device = "main"` and `param = "Setting"`.
+///
+/// ```toml
+/// script = "heater.mips"      # relative to the scenario file
+/// ticks = 100
+/// timeline = "heater.timeline" # optional
+///
+/// [ic]
+/// profile = "ic10"             # or "unlimited", fields below override the profile
+/// pins = 6
+///
+/// [[devices]]
+/// name = "heater"
+/// prefab = "StructureWallHeater"
+/// pin = 0                      # pin of the `main` IC
+/// params = { Temperature = 280 }
+/// behavior = { kind = "heater", rate = 2 }
+///
+/// [[ics]]
+/// name = "logger"
+/// script = "logger.mips"
+/// pins = { d0 = "heater" }
+///
+/// [[expect]]
+/// device = "heater"
+/// param = "Temperature"
+/// min = 290
+///
+/// [[expect]]
+/// ic = "logger"
+/// register = "r0"
+/// min = 290
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub script: Option<String>,
+    pub ticks: usize,
+    #[serde(default)]
+    pub timeline: Option<String>,
+    #[serde(default)]
+    pub ic: ICConfig,
+    #[serde(default)]
+    pub ics: Vec<ICSpec>,
+    /// Order ICs run in each tick, defaults to `main` followed by `ics` in file order
+    #[serde(default)]
+    pub order: Option<Vec<String>>,
+    #[serde(default)]
+    pub networks: Vec<NetworkSpec>,
+    #[serde(default)]
+    pub devices: Vec<DeviceSpec>,
+    #[serde(default)]
+    pub expect: Vec<Expectation>,
+    // Directory relative paths are resolved against
+    #[serde(skip)]
+    base: PathBuf,
+    // Lines run in place of script files, by script path as written in the scenario
+    #[serde(skip)]
+    scripts: HashMap<String, Vec<String>>,
+}
+
+/// Hardware of an IC: a named profile (`ic10` by default), optionally overridden
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ICConfig {
+    pub profile: Option<String>,
+    pub pins: Option<usize>,
+    pub registers: Option<usize>,
+    pub stack_size: Option<usize>,
+    pub max_lines: Option<usize>,
+    pub max_line_length: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub instr_per_tick: Option<usize>,
+}
+
+impl ICConfig {
+    pub fn profile(&self) -> Result<HardwareProfile, String> {
+        let mut p = HardwareProfile::by_name(self.profile.as_deref().unwrap_or("ic10"))?;
+        p.pins = self.pins.unwrap_or(p.pins);
+        p.registers = self.registers.unwrap_or(p.registers);
+        p.stack_size = self.stack_size.unwrap_or(p.stack_size);
+        p.max_lines = self.max_lines.or(p.max_lines);
+        p.max_line_length = self.max_line_length.or(p.max_line_length);
+        p.max_bytes = self.max_bytes.or(p.max_bytes);
+        p.instr_per_tick = self.instr_per_tick.unwrap_or(p.instr_per_tick);
+        Ok(p)
+    }
+
+    fn build(&self) -> Result<ICState, String> {
+        Ok(ICState::from_profile(self.profile()?))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ICSpec {
+    pub name: String,
+    pub script: String,
+    /// Network the IC's housing is connected to
+    #[serde(default)]
+    pub network: Option<String>,
+    #[serde(default)]
+    pub ic: ICConfig,
+    /// Device name mounted on each pin, e.g. `{ d0 = "heater" }`
+    #[serde(default)]
+    pub pins: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkSpec {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceSpec {
+    pub name: String,
+    pub prefab: String,
+    /// Reference id, assigned automatically if not given
+    #[serde(default)]
+    pub id: Option<ReferenceId>,
+    #[serde(default)]
+    pub network: Option<String>,
+    /// Pin of the `main` IC the device is mounted on
+    #[serde(default)]
+    pub pin: Option<usize>,
+    #[serde(default)]
+    pub params: HashMap<String, f64>,
+    /// Parameters of each slot, e.g. `[{ Occupied = 1, Quantity = 5 }]`
+    #[serde(default)]
+    pub slots: Vec<HashMap<String, f64>>,
+    #[serde(default)]
+    pub behavior: Option<BehaviorSpec>,
+}
+
+/// One of the reference behaviours from `behavior`, unset fields take their defaults
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BehaviorSpec {
+    ActiveVent {
+        rate: Option<f64>,
+    },
+    DaylightSensor {
+        day_length: Option<usize>,
+    },
+    Battery,
+    Heater {
+        rate: Option<f64>,
+        ambient: Option<f64>,
+        loss: Option<f64>,
+    },
+}
+
+/// Assertion on a register (of IC `ic`, defaults to `main`) or device parameter.
+///
+/// Checked after tick `tick` if given (before the first tick for `tick = 0`), after every
+/// tick if `always`, otherwise at the end.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    #[serde(default)]
+    pub tick: Option<usize>,
+    #[serde(default)]
+    pub always: bool,
+    #[serde(default)]
+    pub ic: Option<String>,
+    #[serde(default)]
+    pub register: Option<String>,
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub param: Option<String>,
+    #[serde(default)]
+    pub eq: Option<f64>,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+}
+
+/// Index of the network named `name` (or the default network) among `networks`
+fn network_index(networks: &[&str], name: &Option<String>) -> Result<usize, String> {
+    let name = name.as_deref().unwrap_or(DEFAULT_NETWORK);
+    networks
+        .iter()
+        .position(|&n| n == name)
+        .ok_or_else(|| format!("unknown network '{}'", name))
+}
+
+fn default_tolerance() -> f64 {
+    1e-4
+}
+
+/// Outcome of a single expectation
+#[derive(Debug)]
+pub struct ExpectationResult {
+    pub description: String,
+    /// Tick the expectation was (last) checked at
+    pub tick: usize,
+    pub actual: Result<f64, String>,
+    pub passed: bool,
+}
+
+#[derive(Debug)]
+pub struct ScenarioReport {
+    pub ticks: usize,
+    pub results: Vec<ExpectationResult>,
+    /// Error that stopped the simulation early
+    pub error: Option<String>,
+    /// Runtime errors that stopped ICs, as `IC '<name>': <error>`
+    pub ic_errors: Vec<String>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.ic_errors.is_empty() && self.results.iter().all(|r| r.passed)
+    }
+}
+
+impl fmt::Display for ScenarioReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in self.results.iter() {
+            let status = if r.passed { "pass" } else { "FAIL" };
+            match &r.actual {
+                Ok(v) => writeln!(
+                    f,
+                    "[{}] tick {:>4}: {} (actual {})",
+                    status, r.tick, r.description, v
+                )?,
+                Err(e) => writeln!(
+                    f,
+                    "[{}] tick {:>4}: {} ({})",
+                    status, r.tick, r.description, e
+                )?,
+            }
+        }
+        if let Some(e) = &self.error {
+            writeln!(f, "error after {} ticks: {}", self.ticks, e)?;
+        }
+        for e in self.ic_errors.iter() {
+            writeln!(f, "error in {}", e)?;
+        }
+        let npassed = self.results.iter().filter(|r| r.passed).count();
+        write!(f, "{}/{} expectations passed", npassed, self.results.len())
+    }
+}
+
+impl BehaviorSpec {
+    fn apply(&self, dt: &mut DeviceType) {
+        match self {
+            BehaviorSpec::ActiveVent { rate } => {
+                let d = ActiveVent::default();
+                dt.set_behavior(ActiveVent::new(rate.unwrap_or(d.rate)));
+            }
+            BehaviorSpec::DaylightSensor { day_length } => {
+                let d = DaylightSensor::default();
+                dt.set_behavior(DaylightSensor::new(day_length.unwrap_or(d.day_length)));
+            }
+            BehaviorSpec::Battery => dt.set_behavior(Battery),
+            BehaviorSpec::Heater {
+                rate,
+                ambient,
+                loss,
+            } => {
+                let d = Heater::default();
+                dt.set_behavior(Heater::new(
+                    rate.unwrap_or(d.rate),
+                    ambient.unwrap_or(d.ambient),
+                    loss.unwrap_or(d.loss),
+                ));
+            }
+        }
+    }
+}
+
+impl Expectation {
+    fn describe(&self) -> String {
+        let target = match (&self.register, &self.device, &self.param) {
+            (Some(r), _, _) => match &self.ic {
+                Some(ic) => format!("{}.{}", ic, r),
+                None => r.clone(),
+            },
+            (_, Some(d), Some(p)) => format!("{}.{}", d, p),
+            _ => "?".to_owned(),
+        };
+        let mut conds = Vec::new();
+        if let Some(v) = self.eq {
+            conds.push(format!("{} == {}", target, v));
+        }
+        if let Some(v) = self.min {
+            conds.push(format!("{} >= {}", target, v));
+        }
+        if let Some(v) = self.max {
+            conds.push(format!("{} <= {}", target, v));
+        }
+        if self.always {
+            format!("always {}", conds.join(", "))
+        } else {
+            conds.join(", ")
+        }
+    }
+
+    fn validate(&self, scenario: &Scenario) -> Result<(), String> {
+        match (&self.register, &self.device, &self.param) {
+            (Some(_), None, None) => {
+                let ic = self.ic.as_deref().unwrap_or(DEFAULT_IC);
+                if !scenario.ic_names().any(|n| n == ic) {
+                    return Err(format!("Expectation on unknown IC '{}'", ic));
+                }
+            }
+            (None, Some(d), Some(_)) => {
+                let housing = scenario.ic_names().any(|n| n == d);
+                if !housing && !scenario.devices.iter().any(|s| &s.name == d) {
+                    return Err(format!("Expectation on unknown device '{}'", d));
+                }
+            }
+            _ => {
+                return Err(
+                    "Expectation needs either `register` or `device` and `param`".to_owned(),
+                )
+            }
+        }
+        if self.eq.is_none() && self.min.is_none() && self.max.is_none() {
+            return Err(format!(
+                "Expectation '{}' has no `eq`, `min` or `max`",
+                self.describe()
+            ));
+        }
+        Ok(())
+    }
+
+    fn value<I: InstructionSet>(&self, world: &World<I>) -> Result<f64, String> {
+        if let Some(r) = &self.register {
+            let name = self.ic.as_deref().unwrap_or(DEFAULT_IC);
+            let ic = world
+                .ic(name)
+                .ok_or_else(|| format!("Unknown IC '{}'", name))?;
+            ic.get_register(ic.try_register(r)?)
+        } else {
+            let name = self.device.as_deref().unwrap_or("");
+            let d = world
+                .find_device(name)
+                .ok_or_else(|| format!("Unknown device '{}'", name))?;
+            let v = d.borrow().get_param(self.param.as_deref().unwrap_or(""));
+            Ok(v)
+        }
+    }
+
+    fn check<I: InstructionSet>(&self, tick: usize, world: &World<I>) -> ExpectationResult {
+        let actual = self.value(world);
+        let passed = match actual {
+            Ok(v) => {
+                self.eq.is_none_or(|e| (v - e).abs() <= self.tolerance)
+                    && self.min.is_none_or(|m| v >= m - self.tolerance)
+                    && self.max.is_none_or(|m| v <= m + self.tolerance)
+            }
+            Err(_) => false,
+        };
+        ExpectationResult {
+            description: self.describe(),
+            tick,
+            actual,
+            passed,
+        }
+    }
+}
+
+impl Scenario {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let scenario: Scenario =
+            toml::from_str(s).map_err(|e| format!("Invalid scenario: {}", e))?;
+        if scenario.script.is_none() && scenario.ics.is_empty() {
+            return Err("Scenario needs a `script` or `[[ics]]`".to_owned());
+        }
+        for e in scenario.expect.iter() {
+            e.validate(&scenario)?;
+        }
+        Ok(scenario)
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let s = read_to_string(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        let mut scenario = Self::parse(&s)?;
+        scenario.base = Path::new(path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        Ok(scenario)
+    }
+
+    fn resolve(&self, path: &str) -> String {
+        self.base.join(path).to_string_lossy().into_owned()
+    }
+
+    /// Paths of the scripts ICs run, as written in the scenario
+    pub fn script_paths(&self) -> Vec<&str> {
+        let mut paths: Vec<&str> = self.script.iter().map(String::as_str).collect();
+        for spec in self.ics.iter() {
+            if !paths.contains(&spec.script.as_str()) {
+                paths.push(&spec.script);
+            }
+        }
+        paths
+    }
+
+    /// Lines of script `path`, as written in the scenario
+    pub fn script_lines(&self, path: &str) -> Result<Vec<String>, String> {
+        match self.scripts.get(path) {
+            Some(lines) => Ok(lines.clone()),
+            None => read_lines(&self.resolve(path)),
+        }
+    }
+
+    /// Run `lines` in place of the script file `path`, e.g. an optimized version of it
+    pub fn set_script(&mut self, path: &str, lines: Vec<String>) {
+        self.scripts.insert(path.to_owned(), lines);
+    }
+
+    fn ic_names(&self) -> impl Iterator<Item = &str> {
+        self.script
+            .iter()
+            .map(|_| DEFAULT_IC)
+            .chain(self.ics.iter().map(|s| s.name.as_str()))
+    }
+
+    /// Build the world: networks with their devices, and every IC with its pins mounted
+    pub fn build_world(&self) -> Result<World<StationeersInstructionSet>, String> {
+        let mut world = World::new(StationeersInstructionSet::new());
+        let mut networks = vec![DEFAULT_NETWORK];
+        world.add_network(DEFAULT_NETWORK);
+        for n in self.networks.iter().filter(|n| n.name != DEFAULT_NETWORK) {
+            if networks.contains(&n.name.as_str()) {
+                return Err(format!("Network '{}' declared more than once", n.name));
+            }
+            networks.push(&n.name);
+            world.add_network(&n.name);
+        }
+
+        let mut main_pins = Vec::new();
+        for spec in self.devices.iter() {
+            if world.find_device(&spec.name).is_some() {
+                return Err(format!("Device '{}' declared more than once", spec.name));
+            }
+            let n = network_index(&networks, &spec.network)
+                .map_err(|e| format!("Device '{}': {}", spec.name, e))?;
+            let mut dt = DeviceType::new(&spec.prefab);
+            dt.set_label(&spec.name);
+            for (p, v) in spec.params.iter() {
+                dt.set_param(p, *v);
+            }
+            for (i, params) in spec.slots.iter().enumerate() {
+                for (p, v) in params.iter() {
+                    dt.set_slot_param(i, p, *v);
+                }
+            }
+            if let Some(b) = &spec.behavior {
+                b.apply(&mut dt);
+            }
+            let d = world.add_device(n, spec.id, dt)?;
+            if let Some(pin) = spec.pin {
+                main_pins.push((pin, spec.name.as_str(), d));
+            }
+        }
+
+        if let Some(script) = &self.script {
+            let mut ic = self.ic.build()?;
+            for (pin, name, d) in main_pins {
+                ic.try_mount_device(Alias::Device(pin, true), d)
+                    .map_err(|e| format!("Device '{}': {}", name, e))?;
+            }
+            world.add_ic(DEFAULT_IC, 0, ic, self.script_lines(script)?)?;
+        } else if let Some((_, name, _)) = main_pins.first() {
+            return Err(format!(
+                "Device '{}' has a pin but there is no `script`",
+                name
+            ));
+        }
+        for spec in self.ics.iter() {
+            if world.ic(&spec.name).is_some() {
+                return Err(format!("IC '{}' declared more than once", spec.name));
+            }
+            let mut ic = spec.ic.build()?;
+            for (pin, name) in spec.pins.iter() {
+                let d = world
+                    .find_device(name)
+                    .ok_or_else(|| format!("IC '{}': unknown device '{}'", spec.name, name))?
+                    .clone();
+                let a = ic.try_device(pin)?;
+                ic.try_mount_device(a, d)
+                    .map_err(|e| format!("IC '{}': {}", spec.name, e))?;
+            }
+            let n = network_index(&networks, &spec.network)
+                .map_err(|e| format!("IC '{}': {}", spec.name, e))?;
+            world.add_ic(&spec.name, n, ic, self.script_lines(&spec.script)?)?;
+        }
+        if let Some(order) = &self.order {
+            let order: Vec<&str> = order.iter().map(String::as_str).collect();
+            world.set_order(&order)?;
+        }
+        if let Some(t) = &self.timeline {
+            world.set_timeline(Timeline::from_file(&self.resolve(t))?);
+        }
+        Ok(world)
+    }
+
+    /// Run the scenario, checking every expectation
+    pub fn run(&self) -> Result<ScenarioReport, String> {
+        let mut world = self.build_world()?;
+
+        // Results of `always` expectations are kept until their first failure
+        let mut always: Vec<Option<ExpectationResult>> = self.expect.iter().map(|_| None).collect();
+        let mut results = Vec::new();
+        let mut error = None;
+        for (i, e) in self.expect.iter().enumerate() {
+            if !e.always && e.tick == Some(0) {
+                results.push((i, e.check(0, &world)));
+            }
+        }
+        for tick in 1..=self.ticks {
+            if let Err(e) = world.step() {
+                error = Some(e);
+                break;
+            }
+            for (i, e) in self.expect.iter().enumerate() {
+                if e.always {
+                    if always[i].as_ref().is_none_or(|r| r.passed) {
+                        always[i] = Some(e.check(tick, &world));
+                    }
+                } else if e.tick == Some(tick) {
+                    results.push((i, e.check(tick, &world)));
+                }
+            }
+        }
+        let ticks = world.tick();
+        for (i, e) in self.expect.iter().enumerate() {
+            if e.always {
+                if let Some(r) = always[i].take() {
+                    results.push((i, r));
+                }
+            } else if e.tick.is_none() {
+                results.push((i, e.check(ticks, &world)));
+            } else if e.tick.is_some_and(|t| t > ticks) {
+                results.push((
+                    i,
+                    ExpectationResult {
+                        description: e.describe(),
+                        tick: ticks,
+                        actual: Err("tick never reached".to_owned()),
+                        passed: false,
+                    },
+                ));
+            }
+        }
+        results.sort_by_key(|(i, _)| *i);
+        let ic_errors = world
+            .ics()
+            .iter()
+            .filter_map(|e| Some(format!("IC '{}': {}", e.name, e.error.as_ref()?)))
+            .collect();
+        Ok(ScenarioReport {
+            ticks,
+            results: results.into_iter().map(|(_, r)| r).collect(),
+            error,
+            ic_errors,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expectations_on_tick_0_hold_before_the_first_tick() {
+        let toml = r#"
+            script = "script.mips"
+            ticks = 1
+
+            [[devices]]
+            name = "memory"
+            prefab = "StructureLogicMemory"
+            pin = 0
+            params = { Setting = 1 }
+
+            [[expect]]
+            tick = 0
+            device = "memory"
+            param = "Setting"
+            eq = 1
+        "#;
+        let mut scenario = Scenario::parse(toml).unwrap();
+        scenario.set_script("script.mips", vec!["s d0 Setting 2".to_owned()]);
+        let report = scenario.run().unwrap();
+        assert_eq!(report.results.len(), 1);
+        assert!(report.passed(), "{}", report);
+    }
+
+    #[test]
+    fn hashes_of_strings_with_spaces() {
+        let toml = r#"
+            script = "script.mips"
+            ticks = 1
+
+            [[devices]]
+            name = "memory"
+            prefab = "StructureLogicMemory"
+            pin = 0
+
+            [[expect]]
+            device = "memory"
+            param = "Setting"
+            eq = 1
+        "#;
+        let lines = [
+            r#"move r0 HASH("Wall Heater")"#,
+            r#"seq r1 r0 HASH("Wall Heater")"#,
+            "s d0 Setting r1",
+        ];
+        let mut scenario = Scenario::parse(toml).unwrap();
+        scenario.set_script("script.mips", lines.map(str::to_owned).to_vec());
+        let report = scenario.run().unwrap();
+        assert!(report.passed(), "{}", report);
+    }
+
+    #[test]
+    fn runtime_errors_fail_the_scenario() {
+        let toml = r#"
+            script = "script.mips"
+            ticks = 2
+
+            [[devices]]
+            name = "memory"
+            prefab = "StructureLogicMemory"
+            pin = 0
+
+            [[expect]]
+            device = "memory"
+            param = "Setting"
+            eq = 1
+        "#;
+        let mut scenario = Scenario::parse(toml).unwrap();
+        let lines = ["s d0 Setting 1", "s d5 On 1"];
+        scenario.set_script("script.mips", lines.map(str::to_owned).to_vec());
+        let report = scenario.run().unwrap();
+        assert_eq!(report.ticks, 2);
+        assert!(report.results[0].passed);
+        assert_eq!(report.ic_errors.len(), 1);
+        assert!(!report.passed());
+    }
+
+    /// Every scenario of `examples/`, those of `examples/optimize/` being run by the
+    /// optimizer's tests
+    #[test]
+    fn examples() {
+        let mut paths: Vec<_> = std::fs::read_dir("examples")
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.is_dir() && !p.ends_with("optimize"))
+            .flat_map(|dir| std::fs::read_dir(dir).unwrap())
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "toml"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let path = path.to_str().unwrap();
+            let report = Scenario::from_file(path).unwrap().run().unwrap();
+            assert!(report.passed(), "{}:\n{}", path, report);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+
+use crate::profile::HardwareProfile;
+
+/// Measurements of a program against the size limits of a hardware profile
+#[derive(Clone, Debug)]
+pub struct SizeReport {
+    pub profile: HardwareProfile,
+    pub lines: usize,
+    /// Index and length (in characters) of the longest line
+    pub longest_line: Option<(usize, usize)>,
+    /// Size in bytes, newlines included
+    pub bytes: usize,
+    /// Index and length of every line over the profile's line length
+    pub long_lines: Vec<(usize, usize)>,
+}
+
+impl SizeReport {
+    pub fn new(profile: &HardwareProfile, lines: &[String]) -> Self {
+        let lengths = lines.iter().map(|l| l.chars().count()).enumerate();
+        let long_lines = match profile.max_line_length {
+            Some(max) => lengths.clone().filter(|(_, n)| *n > max).collect(),
+            None => Vec::new(),
+        };
+        Self {
+            profile: profile.clone(),
+            lines: lines.len(),
+            longest_line: lengths.max_by_key(|(i, n)| (*n, std::cmp::Reverse(*i))),
+            bytes: lines.join("\n").len(),
+            long_lines,
+        }
+    }
+
+    /// Index of the first line past the profile's line limit, if the program has any
+    pub fn first_overflow_line(&self) -> Option<usize> {
+        self.profile.max_lines.filter(|max| self.lines > *max)
+    }
+
+    /// Every limit the program exceeds
+    pub fn violations(&self) -> Vec<String> {
+        let p = &self.profile;
+        let mut v = Vec::new();
+        if let (Some(i), Some(max)) = (self.first_overflow_line(), p.max_lines) {
+            v.push(format!(
+                "{} lines over the {} line limit (lines {}-{} do not fit)",
+                self.lines - max,
+                max,
+                i,
+                self.lines - 1
+            ));
+        }
+        if let Some(max) = p.max_line_length {
+            for (i, n) in self.long_lines.iter() {
+                v.push(format!(
+                    "line {} has {} characters, over the {} limit",
+                    i, n, max
+                ));
+            }
+        }
+        if let Some(max) = p.max_bytes.filter(|max| self.bytes > *max) {
+            v.push(format!(
+                "{} bytes over the {} byte limit",
+                self.bytes - max,
+                max
+            ));
+        }
+        v
+    }
+
+    pub fn fits(&self) -> bool {
+        self.violations().is_empty()
+    }
+}
+
+fn limit(max: Option<usize>) -> String {
+    max.map_or_else(|| "unlimited".to_owned(), |m| m.to_string())
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let p = &self.profile;
+        writeln!(f, "profile: {}", p.name)?;
+        writeln!(f, "lines: {} / {}", self.lines, limit(p.max_lines))?;
+        match self.longest_line {
+            Some((i, n)) => writeln!(
+                f,
+                "longest line: {} / {} (line {})",
+                n,
+                limit(p.max_line_length),
+                i
+            )?,
+            None => writeln!(f, "longest line: 0 / {}", limit(p.max_line_length))?,
+        }
+        write!(f, "bytes: {} / {}", self.bytes, limit(p.max_bytes))?;
+        for v in self.violations() {
+            write!(f, "\n  {}", v)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(lines: usize, line_length: usize, bytes: usize) -> HardwareProfile {
+        HardwareProfile {
+            max_lines: Some(lines),
+            max_line_length: Some(line_length),
+            max_bytes: Some(bytes),
+            ..HardwareProfile::ic10()
+        }
+    }
+
+    fn report(profile: &HardwareProfile, lines: &[&str]) -> SizeReport {
+        let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        SizeReport::new(profile, &lines)
+    }
+
+    #[test]
+    fn fitting_programs() {
+        let r = report(&profile(3, 4, 13), &["move", "r0 1", "hcf"]);
+        assert_eq!(r.lines, 3);
+        assert_eq!(r.bytes, 13);
+        assert_eq!(r.longest_line, Some((0, 4)));
+        assert_eq!(r.first_overflow_line(), None);
+        assert!(r.long_lines.is_empty());
+        assert!(r.fits());
+
+        let r = report(&profile(3, 4, 13), &[]);
+        assert_eq!((r.lines, r.bytes, r.longest_line), (0, 0, None));
+        assert!(r.fits());
+    }
+
+    #[test]
+    fn overflowing_lines() {
+        let r = report(&profile(2, 90, 4096), &["a", "b", "c", "d"]);
+        assert_eq!(r.first_overflow_line(), Some(2));
+        assert_eq!(
+            r.violations(),
+            ["2 lines over the 2 line limit (lines 2-3 do not fit)"]
+        );
+        assert!(!r.fits());
+    }
+
+    #[test]
+    fn overflowing_line_lengths() {
+        // Lengths are counted in characters, sizes in bytes
+        let r = report(&profile(128, 3, 4096), &["abc", "abcd", "éééé", "ab"]);
+        assert_eq!(r.long_lines, [(1, 4), (2, 4)]);
+        assert_eq!(r.longest_line, Some((1, 4)));
+        assert_eq!(r.bytes, 3 + 4 + 8 + 2 + 3);
+        assert_eq!(
+            r.violations(),
+            [
+                "line 1 has 4 characters, over the 3 limit",
+                "line 2 has 4 characters, over the 3 limit"
+            ]
+        );
+    }
+
+    #[test]
+    fn overflowing_bytes() {
+        let r = report(&profile(128, 90, 6), &["abc", "abc"]);
+        assert_eq!(r.violations(), ["1 bytes over the 6 byte limit"]);
+        let r = report(&profile(128, 90, 7), &["abc", "abc"]);
+        assert!(r.fits());
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let r = report(&profile(1, 2, 4), &["abc", "d"]);
+        assert_eq!(
+            r.to_string(),
+            "profile: ic10\n\
+             lines: 2 / 1\n\
+             longest line: 3 / 2 (line 0)\n\
+             bytes: 5 / 4\n  \
+             1 lines over the 1 line limit (lines 1-1 do not fit)\n  \
+             line 0 has 3 characters, over the 2 limit\n  \
+             1 bytes over the 4 byte limit"
+        );
+    }
+}
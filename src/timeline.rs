@@ -0,0 +1,202 @@
+use std::fs::read_to_string;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref PATTERN_TICK: Regex = Regex::new(r"^tick\s+(\d+)\s*:\s*(.*)$").unwrap();
+    static ref PATTERN_ASSIGN: Regex = Regex::new(r"^(\w+)\.(\w+)\s*=\s*(\S+)$").unwrap();
+}
+
+/// A single scripted device parameter change
+#[derive(Clone, Debug)]
+pub struct TimelineEvent {
+    pub tick: usize,
+    pub device: String,
+    pub param: String,
+    pub value: f64,
+}
+
+/// Scripted device inputs, applied by a `World` before each tick.
+///
+/// Two formats are accepted, one entry per line (or `;` separated), `#` starts a comment:
+///
+/// * `tick 0: d0.Temperature = 290, d1.On = 1`
+/// * CSV rows of `tick,device,param,value` (an optional header row is skipped)
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    // Sorted by tick, stable w.r.t. file order
+    events: Vec<TimelineEvent>,
+}
+
+impl Timeline {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        let entries = s
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or(""))
+            .flat_map(|l| l.split(';'))
+            .map(str::trim)
+            .filter(|e| !e.is_empty());
+        for entry in entries {
+            if let Some(caps) = PATTERN_TICK.captures(entry) {
+                let tick = parse_tick(&caps[1])?;
+                for assign in caps[2].split(',').map(str::trim).filter(|a| !a.is_empty()) {
+                    let caps = PATTERN_ASSIGN
+                        .captures(assign)
+                        .ok_or_else(|| format!("Invalid timeline assignment '{}'", assign))?;
+                    events.push(TimelineEvent {
+                        tick,
+                        device: caps[1].to_owned(),
+                        param: caps[2].to_owned(),
+                        value: parse_value(&caps[3])?,
+                    });
+                }
+            } else {
+                let fields: Vec<&str> = entry.split(',').map(str::trim).collect();
+                match fields.as_slice() {
+                    ["tick", ..] => {} // header
+                    [tick, device, param, value] => events.push(TimelineEvent {
+                        tick: parse_tick(tick)?,
+                        device: (*device).to_owned(),
+                        param: (*param).to_owned(),
+                        value: parse_value(value)?,
+                    }),
+                    _ => return Err(format!("Invalid timeline entry '{}'", entry)),
+                }
+            }
+        }
+        events.sort_by_key(|e| e.tick);
+        Ok(Self { events })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let s = read_to_string(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        Self::parse(&s)
+    }
+
+    pub fn events(&self) -> &[TimelineEvent] {
+        &self.events
+    }
+
+    /// Events scheduled for tick `tick`
+    pub fn events_at(&self, tick: usize) -> impl Iterator<Item = &TimelineEvent> {
+        self.events.iter().filter(move |e| e.tick == tick)
+    }
+}
+
+fn parse_tick(s: &str) -> Result<usize, String> {
+    s.parse()
+        .map_err(|_| format!("Invalid timeline tick '{}'", s))
+}
+
+fn parse_value(s: &str) -> Result<f64, String> {
+    s.parse()
+        .map_err(|_| format!("Invalid timeline value '{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(s: &str) -> Vec<(usize, String, String, f64)> {
+        let timeline = Timeline::parse(s).unwrap();
+        timeline
+            .events()
+            .iter()
+            .map(|e| (e.tick, e.device.clone(), e.param.clone(), e.value))
+            .collect()
+    }
+
+    fn event(tick: usize, device: &str, param: &str, value: f64) -> (usize, String, String, f64) {
+        (tick, device.to_owned(), param.to_owned(), value)
+    }
+
+    #[test]
+    fn tick_lines() {
+        let s = "
+            # Comment
+            tick 2: d0.On = 0
+            tick 0: d0.Temperature = 290, d1.On = 1 # trailing comment
+        ";
+        assert_eq!(
+            events(s),
+            [
+                event(0, "d0", "Temperature", 290.0),
+                event(0, "d1", "On", 1.0),
+                event(2, "d0", "On", 0.0),
+            ]
+        );
+        assert_eq!(
+            events("tick 1: d0.On = 1; tick 0:d0.Mode=2"),
+            [event(0, "d0", "Mode", 2.0), event(1, "d0", "On", 1.0)]
+        );
+    }
+
+    #[test]
+    fn csv_rows() {
+        let s = "
+            tick,device,param,value
+            3, vent, On, 1
+            1,vent,Pressure,-2.5
+        ";
+        assert_eq!(
+            events(s),
+            [
+                event(1, "vent", "Pressure", -2.5),
+                event(3, "vent", "On", 1.0),
+            ]
+        );
+        assert_eq!(
+            events("0,d0,On,1; 0,d1,On,0"),
+            [event(0, "d0", "On", 1.0), event(0, "d1", "On", 0.0)]
+        );
+    }
+
+    #[test]
+    fn formats_mix_and_keep_file_order_within_a_tick() {
+        let s = "
+            1,d0,On,1
+            tick 1: d0.On = 0
+            0,d0,On,2
+        ";
+        assert_eq!(
+            events(s),
+            [
+                event(0, "d0", "On", 2.0),
+                event(1, "d0", "On", 1.0),
+                event(1, "d0", "On", 0.0),
+            ]
+        );
+        assert_eq!(
+            Timeline::parse("tick 4: d0.On = 1")
+                .unwrap()
+                .events_at(4)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn malformed_entries() {
+        let errors = [
+            (
+                "tick -1: d0.On = 1",
+                "Invalid timeline entry 'tick -1: d0.On = 1'",
+            ),
+            ("tick 0: d0 = 1", "Invalid timeline assignment 'd0 = 1'"),
+            ("tick 0: d0.On = on", "Invalid timeline value 'on'"),
+            (
+                "tick 0: d0.On = 1 2",
+                "Invalid timeline assignment 'd0.On = 1 2'",
+            ),
+            ("x,d0,On,1", "Invalid timeline tick 'x'"),
+            ("0,d0,On", "Invalid timeline entry '0,d0,On'"),
+            ("0,d0,On,1,2", "Invalid timeline entry '0,d0,On,1,2'"),
+            ("0,d0,On,", "Invalid timeline value ''"),
+        ];
+        for (s, error) in errors {
+            assert_eq!(Timeline::parse(s).unwrap_err(), error, "{}", s);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    device::{self, DeviceRef, DeviceType},
+    ic::ICState,
+    instruction::InstructionSet,
+    load_program,
+    network::{Network, NetworkRef, ReferenceId},
+    timeline::Timeline,
+    try_run_tick,
+};
+
+/// An IC running its program inside a `World`
+pub struct WorldIC {
+    pub name: String,
+    pub ic: ICState,
+    lines: Vec<String>,
+    /// Runtime error that stopped the IC, also raising its housing's `Error`
+    pub error: Option<String>,
+}
+
+/// Simulation of several ICs sharing the devices of one or more networks.
+///
+/// Each game tick runs every IC to completion in a deterministic order (insertion order
+/// unless set with `set_order`), then advances every device exactly once. ICs later in the
+/// order see the device writes of earlier ones within the same tick. A runtime error only
+/// stops the IC it happened on, the others keep running.
+pub struct World<I: InstructionSet> {
+    networks: Vec<NetworkRef>,
+    ics: Vec<WorldIC>,
+    order: Vec<usize>,
+    instructions: I,
+    timeline: Option<Timeline>,
+    next_id: ReferenceId,
+    tick: usize,
+}
+
+impl<I: InstructionSet> World<I> {
+    pub fn new(instructions: I) -> Self {
+        Self {
+            networks: Vec::new(),
+            ics: Vec::new(),
+            order: Vec::new(),
+            instructions,
+            timeline: None,
+            next_id: 1,
+            tick: 0,
+        }
+    }
+
+    /// Add an empty network, returning its index
+    pub fn add_network(&mut self, name: &str) -> usize {
+        self.networks
+            .push(Rc::new(RefCell::new(Network::new(name))));
+        self.networks.len() - 1
+    }
+
+    pub fn network(&self, name: &str) -> Option<&NetworkRef> {
+        self.networks.iter().find(|n| n.borrow().name() == name)
+    }
+
+    pub fn networks(&self) -> &[NetworkRef] {
+        &self.networks
+    }
+
+    fn is_id_used(&self, id: ReferenceId) -> bool {
+        self.networks
+            .iter()
+            .any(|n| n.borrow().get_device(id).is_some())
+    }
+
+    /// Reserve reference id `id`, or the next unused one
+    fn allocate_id(&mut self, id: Option<ReferenceId>) -> Result<ReferenceId, String> {
+        match id {
+            Some(id) if self.is_id_used(id) => Err(format!("Reference id '{}' already in use", id)),
+            Some(id) => Ok(id),
+            None => {
+                while self.is_id_used(self.next_id) {
+                    self.next_id += 1;
+                }
+                self.next_id += 1;
+                Ok(self.next_id - 1)
+            }
+        }
+    }
+
+    fn try_network(&self, network: usize) -> Result<NetworkRef, String> {
+        self.networks
+            .get(network)
+            .cloned()
+            .ok_or_else(|| format!("Invalid network index '{}'", network))
+    }
+
+    /// Connect a new device to network `network` under reference id `id`, or a unique one
+    pub fn add_device(
+        &mut self,
+        network: usize,
+        id: Option<ReferenceId>,
+        dt: DeviceType,
+    ) -> Result<DeviceRef, String> {
+        let n = self.try_network(network)?;
+        let id = self.allocate_id(id)?;
+        let d = n.borrow_mut().add_device(id, dt);
+        Ok(d)
+    }
+
+    /// First device labelled `label` on any network
+    pub fn find_device(&self, label: &str) -> Option<DeviceRef> {
+        self.networks
+            .iter()
+            .find_map(|n| n.borrow().find_device(label).cloned())
+    }
+
+    /// Add an IC running `lines`, its housing labelled `name` and connected to network
+    /// `network`, whose devices the IC can then reach by reference id and hashes.
+    /// Returns the IC's index.
+    pub fn add_ic(
+        &mut self,
+        name: &str,
+        network: usize,
+        mut ic: ICState,
+        lines: Vec<String>,
+    ) -> Result<usize, String> {
+        load_program(&mut ic, &lines).map_err(|e| format!("IC '{}': {}", name, e))?;
+        let n = self.try_network(network)?;
+        let id = self.allocate_id(None)?;
+        ic.housing().borrow_mut().set_label(name);
+        n.borrow_mut().connect(id, ic.housing().clone());
+        ic.set_network(n);
+        self.ics.push(WorldIC {
+            name: name.to_owned(),
+            ic,
+            lines,
+            error: None,
+        });
+        self.order.push(self.ics.len() - 1);
+        Ok(self.ics.len() - 1)
+    }
+
+    pub fn ic(&self, name: &str) -> Option<&ICState> {
+        self.ics.iter().find(|e| e.name == name).map(|e| &e.ic)
+    }
+
+    pub fn ic_mut(&mut self, name: &str) -> Option<&mut ICState> {
+        self.ics
+            .iter_mut()
+            .find(|e| e.name == name)
+            .map(|e| &mut e.ic)
+    }
+
+    pub fn ics(&self) -> &[WorldIC] {
+        &self.ics
+    }
+
+    /// Set the order ICs run in each tick. Every IC must be named exactly once.
+    pub fn set_order(&mut self, names: &[&str]) -> Result<(), String> {
+        let mut order = Vec::new();
+        for name in names {
+            let i = self
+                .ics
+                .iter()
+                .position(|e| &e.name == name)
+                .ok_or_else(|| format!("Unknown IC '{}'", name))?;
+            if order.contains(&i) {
+                return Err(format!("IC '{}' ordered more than once", name));
+            }
+            order.push(i);
+        }
+        if order.len() != self.ics.len() {
+            return Err("Every IC must be given an order".to_owned());
+        }
+        self.order = order;
+        Ok(())
+    }
+
+    /// Scripted device inputs applied before each tick.
+    ///
+    /// Devices are looked up by label, then as pin aliases of the first IC.
+    pub fn set_timeline(&mut self, timeline: Timeline) {
+        self.timeline = Some(timeline);
+    }
+
+    /// Number of ticks run so far
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+
+    fn apply_timeline(&mut self) -> Result<(), String> {
+        if let Some(timeline) = &self.timeline {
+            for e in timeline.events_at(self.tick) {
+                if let Some(d) = self.find_device(&e.device) {
+                    d.borrow_mut().set_param(&e.param, e.value);
+                } else if let Some(first) = self.ics.first_mut() {
+                    let d = first.ic.try_device(&e.device)?;
+                    first.ic.try_set_device_param(d, &e.param, e.value)?;
+                } else {
+                    return Err(format!("Unknown timeline device '{}'", e.device));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply this tick's timeline events, run every IC in order, then advance every device.
+    ///
+    /// Fails on timeline events for unknown devices, runtime errors are kept on the IC.
+    pub fn step(&mut self) -> Result<(), String> {
+        self.apply_timeline()?;
+        for &i in self.order.iter() {
+            let e = &mut self.ics[i];
+            if let Err(err) = try_run_tick(&mut e.ic, &e.lines, &self.instructions) {
+                e.error = Some(err);
+            }
+        }
+        let network_devices: Vec<DeviceRef> = self
+            .networks
+            .iter()
+            .flat_map(|n| {
+                let n = n.borrow();
+                n.devices().map(|(_, d)| d.clone()).collect::<Vec<_>>()
+            })
+            .collect();
+        let pin_devices = self.ics.iter().flat_map(|e| e.ic.devices());
+        device::tick_devices(network_devices.iter().chain(pin_devices));
+        self.tick += 1;
+        Ok(())
+    }
+
+    /// Run `n` ticks, stopping at the first error.
+    pub fn run(&mut self, n: usize) -> Result<(), String> {
+        for _ in 0..n {
+            self.step()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{behavior::Heater, device::DeviceType, instruction::StationeersInstructionSet};
+
+    /// World of ICs running `scripts`, each with the same memory on `d0`
+    fn world(scripts: &[(&str, &[&str])]) -> (World<StationeersInstructionSet>, DeviceRef) {
+        let mut world = World::new(StationeersInstructionSet::new());
+        let n = world.add_network("base");
+        let mut memory = DeviceType::new("StructureLogicMemory");
+        memory.set_label("memory");
+        memory.set_param("Setting", 1.0);
+        let memory = world.add_device(n, None, memory).unwrap();
+        for (name, lines) in scripts {
+            let mut ic = ICState::default();
+            let d0 = ic.try_device("d0").unwrap();
+            ic.try_mount_device(d0, memory.clone()).unwrap();
+            let lines = lines.iter().map(|l| l.to_string()).collect();
+            world.add_ic(name, n, ic, lines).unwrap();
+        }
+        (world, memory)
+    }
+
+    const ADD: &[&str] = &["l r0 d0 Setting", "add r0 r0 1", "s d0 Setting r0"];
+    const DOUBLE: &[&str] = &["l r0 d0 Setting", "mul r0 r0 2", "s d0 Setting r0"];
+
+    #[test]
+    fn ics_run_in_order() {
+        let (mut w, memory) = world(&[("add", ADD), ("double", DOUBLE)]);
+        w.step().unwrap();
+        assert_eq!(memory.borrow().get_param("Setting"), 4.0);
+
+        let (mut w, memory) = world(&[("add", ADD), ("double", DOUBLE)]);
+        w.set_order(&["double", "add"]).unwrap();
+        w.step().unwrap();
+        assert_eq!(memory.borrow().get_param("Setting"), 3.0);
+        assert_eq!(w.tick(), 1);
+    }
+
+    #[test]
+    fn orders_name_every_ic_once() {
+        let (mut w, _) = world(&[("add", ADD), ("double", DOUBLE)]);
+        assert_eq!(w.set_order(&["add", "x"]), Err("Unknown IC 'x'".to_owned()));
+        assert_eq!(
+            w.set_order(&["add", "add"]),
+            Err("IC 'add' ordered more than once".to_owned())
+        );
+        assert_eq!(
+            w.set_order(&["add"]),
+            Err("Every IC must be given an order".to_owned())
+        );
+    }
+
+    #[test]
+    fn shared_devices_tick_once() {
+        let (mut w, memory) = world(&[("a", &[]), ("b", &[])]);
+        memory.borrow_mut().set_behavior(Heater::new(1.0, 0.0, 0.0));
+        memory.borrow_mut().set_param("On", 1.0);
+        w.run(3).unwrap();
+        assert_eq!(memory.borrow().get_param("Temperature"), 3.0);
+    }
+
+    #[test]
+    fn runtime_errors_stop_their_ic_only() {
+        let failing: &[&str] = &["yield", "s d5 On 1"];
+        let (mut w, memory) = world(&[
+            ("failing", failing),
+            ("add", &["loop:", ADD[0], ADD[1], ADD[2], "yield", "j loop"]),
+        ]);
+        w.step().unwrap();
+        assert!(w.ics()[0].error.is_none());
+        w.run(2).unwrap();
+        assert!(w.ics()[0].error.is_some());
+        assert!(w.ic("failing").unwrap().has_error());
+        assert!(!w.ic("add").unwrap().has_error());
+        assert_eq!(memory.borrow().get_param("Setting"), 4.0);
+    }
+
+    #[test]
+    fn timelines_set_devices_by_label_or_pin() {
+        let (mut w, memory) = world(&[("a", &[])]);
+        w.set_timeline(Timeline::parse("tick 0: memory.On = 1; tick 1: d0.Mode = 2").unwrap());
+        w.step().unwrap();
+        assert_eq!(memory.borrow().get_param("On"), 1.0);
+        w.step().unwrap();
+        assert_eq!(memory.borrow().get_param("Mode"), 2.0);
+
+        w.set_timeline(Timeline::parse("tick 2: nothing.On = 1").unwrap());
+        assert!(w.step().is_err());
+    }
+}